@@ -1,40 +1,62 @@
-use serde::{Deserialize, Serialize};
-use serde_json;
+use chrono::{Datelike, TimeZone, Timelike};
+use chrono_tz::Tz;
 use serenity::http::error::Error::UnsuccessfulRequest;
 use serenity::http::Http;
-use serenity::model::id::ChannelId;
+use serenity::model::channel::AttachmentType;
+use serenity::model::id::{AttachmentId, ChannelId};
 use serenity::prelude::GatewayIntents;
 use serenity::Client;
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{BufReader, Result};
+use std::collections::{HashMap, HashSet};
+use std::io::Result;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
-use crate::dcs::{Servers, Server, ServersMessage};
+use crate::chart::{heatmap, line_chart, Series};
+use crate::config::{
+    next_name, Config, OfflineAction, ReleaseSub, ServerMessage, SortKey, SortOrder, Style, Sub,
+    SubMode, DEFAULT_LIMIT, MAX_LIMIT, MAX_SERVER_MESSAGES,
+};
+use crate::dcs::{
+    Branch, Changelog, DcsVersion, Release, Server, ServerEvent, ServerId, Servers, ServersMessage,
+};
+use crate::embed::{self, Embed, Page};
+use crate::filter::SubFilter;
 use crate::handler::{Handler, HandlerMessage};
+use crate::history::{History, ServerStats};
+use crate::{fuzzy, normalize};
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct Sub {
-    pub message_id: u64,
-    pub filter: String,
-    pub last_content: String,
+// Discord's limit on a message's length, in characters; listings longer than
+// this are split over several messages, up to MAX_PAGES
+const MESSAGE_LIMIT: usize = 2000;
+const MAX_PAGES: usize = 5;
+const FOOTER_ROOM: usize = 32;
+
+/**
+ * What's gone when editing a message fails, if that's why it failed
+ */
+enum Missing {
+    Message,
+    Channel,
 }
 
 pub struct Bot {
     token: String,
     servers_rx: mpsc::Receiver<ServersMessage>,
-    versions: HashMap<String, String>,
+    versions: HashMap<DcsVersion, Branch>, // the newest release on each branch
     config_path: String,
-    channels: HashMap<u64, Sub>, // channel_id : message_id mappings
+    config: Config,
+    history: Option<History>,
+    changed: HashMap<ServerId, i64>, // when each server last changed, for sorting by it
 }
 
 impl Bot {
     pub fn new(
         token: String,
         mut config_path: String,
+        history: Option<History>,
         servers_rx: mpsc::Receiver<ServersMessage>,
     ) -> Self {
-        if config_path.eq("") {
+        if config_path.is_empty() {
             config_path = "config.json".to_string();
         }
 
@@ -43,151 +65,502 @@ impl Bot {
             servers_rx,
             versions: HashMap::new(),
             config_path,
-            channels: HashMap::new(),
-        }
-    }
-    /**
-     * Turns the DCS-provided strings into something usable
-     */
-    fn sanitize_name(&self, name: &str) -> String {
-        // Get rid of the decorations people use; this will probably
-        // mess up non-English names, so need to be more artful here
-        let mut fixed = name.replace(|c: char| !c.is_ascii(), "");
-
-        // Convert HTML special chars
-        fixed = fixed.replace("&amp;", "&");
-        fixed = fixed.replace("&gt;", ">");
-        fixed = fixed.replace("&lt;", "<");
-
-        // ED adds spaces to allow linebreaks on the DCS website
-        // we can't tell if this is added by them or part of the
-        // actual data, so if the server owner intentionally had
-        // a space at char 20, this will sadly remove it
-        if Some(20) == fixed.find(" ") {
-            fixed = fixed.replacen(" ", "", 1);
+            config: Config::default(),
+            history,
+            changed: HashMap::new(),
         }
-
-        fixed.trim().to_string()
     }
 
     // These format functions are probably slow, and might be made
     // better with static strings
-    fn format_players(&self, players: &str) -> String {
-        match players.parse::<i32>().unwrap() - 1 {
+    fn format_players(&self, players: u32) -> String {
+        // The website counts the server itself as a player
+        match players.saturating_sub(1) {
             0 => String::from("0 players"),
             1 => String::from("__1 player__"),
             x => format!("__{} players__", x),
         }
     }
 
-    // See set_versions for string definitions
-    fn format_version(&self, version: &str) -> String {
+    // Names the branch for the newest releases; see set_versions
+    fn format_version(&self, version: &DcsVersion) -> String {
         match self.versions.get(version) {
-            Some(vstr) => String::from(vstr),
-            None => String::from(version),
+            Some(branch) => format!("{} ({})", branch, version),
+            None => version.to_string(),
+        }
+    }
+
+    /**
+     * The servers that a subscription's filter picks out
+     */
+    fn matching_servers<'a>(&self, servers: &'a Servers, sub: &Sub) -> Vec<&'a Server> {
+        servers
+            .SERVERS
+            .iter()
+            .filter(|server| sub.matcher.matches(server, sub.fuzzy))
+            .collect()
+    }
+
+    /**
+     * The names of the servers that come closest to the names a subscription
+     * is looking for, to suggest when it matches nothing
+     */
+    fn near_misses(&self, servers: &Servers, sub: &Sub) -> Vec<String> {
+        let names = servers
+            .SERVERS
+            .iter()
+            .map(|server| normalize::display_name(&server.NAME));
+        fuzzy::suggestions(&sub.matcher.name_terms().join(" "), names, 3)
+    }
+
+    /**
+     * What to show in place of the listing when nothing matches
+     */
+    fn render_no_matches(&self, servers: &Servers, sub: &Sub) -> String {
+        let mut output = format!("No servers match '{}' right now", sub.describe());
+        let suggestions = self.near_misses(servers, sub);
+        if !suggestions.is_empty() {
+            output.push_str(". Did you mean:\n");
+            for name in suggestions {
+                output.push_str(&format!("- **{}**\n", name));
+            }
+            if !sub.fuzzy {
+                output.push_str("\nTo allow for typos, type `!dcsbot set fuzzy on`");
+            }
         }
+        output
     }
 
     /**
-     * Takes a list of all the servers, finds the one matching <filter>, and
-     * renders the result into Discord-friendly markdown
+     * Sorts servers for a listing; servers that tie stay in name order
      */
-    fn render_servers(&self, servers: &Servers, filter: &String) -> (usize, std::string::String) {
-        let mut sorted = Vec::<&Server>::new();
-        for server in &servers.SERVERS {
-            if !server.NAME.to_lowercase().contains(filter.to_lowercase().as_str()) {
-                continue;
+    fn sort_servers(&self, servers: &mut [&Server], key: SortKey, order: SortOrder) {
+        servers
+            .sort_by_cached_key(|server| normalize::fold(&normalize::display_name(&server.NAME)));
+        if key == SortKey::Name {
+            if order == SortOrder::Desc {
+                servers.reverse();
             }
-            sorted.push(server);
+            return;
+        }
+
+        servers.sort_by(|a, b| {
+            let ordering = match key {
+                SortKey::Players => a.PLAYERS.cmp(&b.PLAYERS),
+                SortKey::Name => unreachable!("name order is handled above"),
+                SortKey::Version => a.DCS_VERSION.cmp(&b.DCS_VERSION),
+                SortKey::Uptime => a.MISSION_TIME.cmp(&b.MISSION_TIME),
+                SortKey::Changed => {
+                    let changed = |server: &Server| self.changed.get(&server.id).copied();
+                    changed(a).cmp(&changed(b))
+                }
+            };
+            match order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+    }
+
+    /**
+     * One server's entry in a listing
+     */
+    fn render_server(&self, server: &Server) -> String {
+        format!(
+            "**{} - {}**\n\
+            {}, {}, {}:{}",
+            normalize::display_name(&server.NAME),
+            normalize::display_name(&server.MISSION_NAME),
+            self.format_players(server.PLAYERS),
+            self.format_version(&server.DCS_VERSION),
+            server.IP_ADDRESS,
+            server.PORT,
+        )
+    }
+
+    /**
+     * Takes a list of all the servers, finds the ones matching <filter>, and
+     * renders the result into Discord-friendly markdown, split into as many
+     * messages as it needs
+     */
+    fn render_servers(&self, servers: &Servers, sub: &Sub) -> (usize, Vec<Page>) {
+        let mut sorted = self.matching_servers(servers, sub);
+        if sorted.is_empty() {
+            return (0, vec![Page::text(self.render_no_matches(servers, sub))]);
         }
+        self.sort_servers(&mut sorted, sub.sort, sub.order);
+        let total = sorted.len();
+        sorted.truncate(sub.limit);
+
+        let pages = match sub.style {
+            Style::Markdown => {
+                let output = sorted
+                    .iter()
+                    .map(|server| format!("{}\n\n", self.render_server(server)))
+                    .collect();
+                paginate(output, total)
+                    .into_iter()
+                    .map(Page::text)
+                    .collect()
+            }
+            Style::Embeds => {
+                let embeds = sorted.iter().map(|server| self.server_embed(server));
+                paginate_embeds(embeds.collect(), total)
+            }
+            Style::Fields => self.render_fields(&sorted, total, sub),
+        };
+        (total, pages)
+    }
+
+    /**
+     * The newest branch a server's version is the latest release of, if any
+     */
+    fn branch(&self, server: &Server) -> Option<Branch> {
+        self.versions.get(&server.DCS_VERSION).copied()
+    }
 
-        // Without semver understanding, this might do all kinds of stuff, but
-        // for now it's OK because it will at least group the servers together
-        sorted.sort_by_cached_key(|a| a.DCS_VERSION.clone());
-        sorted.reverse();
+    /**
+     * How many are playing on a server and how full it is, as a bar
+     */
+    fn format_bar(&self, server: &Server) -> String {
+        // The website counts the server itself as a player
+        let players = server.PLAYERS.saturating_sub(1);
+        format!(
+            "`{}` {}/{}",
+            embed::player_bar(players, server.PLAYERS_MAX),
+            players,
+            server.PLAYERS_MAX
+        )
+    }
+
+    /**
+     * A server's name, with a lock if it needs a password
+     */
+    fn format_title(&self, server: &Server) -> String {
+        let name = normalize::display_name(&server.NAME);
+        match server.PASSWORD {
+            true => format!("🔒 {}", name),
+            false => name,
+        }
+    }
 
-        let mut output = Vec::<String>::new();
-        for server in &sorted {
-            output.push(format!(
-                "**{} - {}**\n\
-                {}, {}, {}:{}\n\n",
-                self.sanitize_name(&server.NAME),
-                self.sanitize_name(&server.MISSION_NAME),
-                self.format_players(&server.PLAYERS),
+    /**
+     * A server as an embed of its own, coloured by branch
+     */
+    fn server_embed(&self, server: &Server) -> Embed {
+        Embed {
+            title: self.format_title(server),
+            description: format!(
+                "*{}*\n{}\n{}\n`{}:{}`",
+                normalize::display_name(&server.MISSION_NAME),
+                self.format_bar(server),
                 self.format_version(&server.DCS_VERSION),
                 server.IP_ADDRESS,
                 server.PORT,
-            ));
+            ),
+            colour: embed::branch_colour(self.branch(server)),
+            ..Default::default()
+        }
+    }
 
-            if output.len() > 10 {
-                break;
+    /**
+     * Servers as fields of one embed per message, each marked with its
+     * branch's colour
+     */
+    fn render_fields(&self, servers: &[&Server], total: usize, sub: &Sub) -> Vec<Page> {
+        let title = format!("Servers matching '{}'", sub.describe());
+        let new_embed = |title: &str| Embed {
+            title: title.to_string(),
+            colour: embed::LISTING,
+            ..Default::default()
+        };
+
+        let mut embeds = vec![new_embed(&title)];
+        let mut shown = 0;
+        for server in servers {
+            let field = (
+                format!(
+                    "{} {}",
+                    embed::branch_marker(self.branch(server)),
+                    self.format_title(server)
+                ),
+                format!(
+                    "*{}*\n{} · {}\n`{}:{}`",
+                    normalize::display_name(&server.MISSION_NAME),
+                    self.format_bar(server),
+                    self.format_version(&server.DCS_VERSION),
+                    server.IP_ADDRESS,
+                    server.PORT,
+                ),
+            );
+            let size = field.0.chars().count() + field.1.chars().count();
+
+            let embed = embeds.last_mut().unwrap();
+            if embed.fields.len() == embed::MAX_FIELDS
+                || (!embed.fields.is_empty()
+                    && embed.size() + size > embed::EMBED_LIMIT - FOOTER_ROOM)
+            {
+                if embeds.len() == MAX_PAGES {
+                    break;
+                }
+                embeds.push(new_embed(&format!("{} (continued)", title)));
             }
+            embeds.last_mut().unwrap().fields.push(field);
+            shown += 1;
+        }
+
+        embeds.last_mut().unwrap().footer = more_servers(total - shown);
+        embeds
+            .into_iter()
+            .map(|embed| Page {
+                content: String::new(),
+                embeds: vec![embed],
+            })
+            .collect()
+    }
+
+    /**
+     * For a subscription with a message per server: the servers to give
+     * messages to, and the text of the message above them
+     */
+    fn render_server_messages<'a>(
+        &self,
+        servers: &'a Servers,
+        sub: &Sub,
+    ) -> (usize, Vec<&'a Server>, String) {
+        let mut sorted = self.matching_servers(servers, sub);
+        if sorted.is_empty() {
+            return (0, sorted, self.render_no_matches(servers, sub));
+        }
+        self.sort_servers(&mut sorted, sub.sort, sub.order);
+
+        let total = sorted.len();
+        sorted.truncate(sub.limit);
+        let mut header = match total {
+            1 => format!("1 server matches '{}'", sub.describe()),
+            total => format!("{} servers match '{}'", total, sub.describe()),
+        };
+        if sorted.len() < total {
+            header.push_str(&format!(", showing the first {}", sorted.len()));
         }
+        (total, sorted, header)
+    }
 
-        // Crop output to discord limits
-        let mut string = output.join("");
-        if string.len() > 1999 {
-            string = string.split_at(1999).0.to_string();
+    /**
+     * Finds the subscription a command is about: the one with the given name,
+     * or the channel's only subscription if no name was given. Otherwise
+     * returns what to tell the user
+     */
+    fn find_sub(&self, channel_id: u64, name: Option<&str>) -> std::result::Result<usize, String> {
+        let subs = match self.config.channels.get(&channel_id) {
+            Some(subs) if !subs.is_empty() => subs,
+            _ => return Err("This channel has no subscriptions".to_string()),
+        };
+        match name {
+            Some(name) => subs
+                .iter()
+                .position(|sub| sub.name == name)
+                .ok_or_else(|| format!("This channel has no subscription called `{}`", name)),
+            None if subs.len() == 1 => Ok(0),
+            None => Err(format!(
+                "This channel has {} subscriptions, so say which one (see `!dcsbot list`)",
+                subs.len()
+            )),
+        }
+    }
+
+    /**
+     * The subscription a `set` command changes, found as find_sub does. If
+     * there isn't one, tells the channel why and gives None
+     */
+    async fn sub_to_set(
+        &mut self,
+        http: &Http,
+        channel_id: u64,
+        name: Option<&str>,
+    ) -> Option<&mut Sub> {
+        match self.find_sub(channel_id, name) {
+            Ok(index) => Some(&mut self.config.channels.get_mut(&channel_id)?[index]),
+            Err(reply) => {
+                let _ = ChannelId(channel_id).say(http, reply).await;
+                None
+            }
         }
-        (sorted.len(), string)
     }
 
     /**
      * Subscribes to a channel - will create a message in that channel to post to; if
      * that is unsuccessful, the subscribe will fail, otherwise we will track the
-     * channel_id/message_id/filter
+     * channel_id/message_id/filter. Subscribing again with a name that's taken
+     * replaces that subscription
      */
-    async fn subscribe_channel(&mut self, http: &Http, channel_id: u64, filter: String) {
+    async fn subscribe_channel(
+        &mut self,
+        http: &Http,
+        channel_id: u64,
+        name: Option<String>,
+        regex: Option<String>,
+        filter: String,
+        matcher: SubFilter,
+    ) {
         println!("\x1b[32mSubscribing to channel {}\x1b[0m", channel_id);
+        if let Some(name) = &name {
+            self.remove_sub(http, channel_id, name).await;
+        }
+        let subs = self
+            .config
+            .channels
+            .get(&channel_id)
+            .map_or(&[][..], Vec::as_slice);
+        let mut sub = Sub {
+            name: name.unwrap_or_else(|| next_name(subs)),
+            message_id: 0,
+            page_ids: vec![],
+            filter,
+            query: true,
+            regex,
+            matcher,
+            last_content: String::new(),
+            chart: false,
+            chart_attachment: None,
+            chart_updated: 0,
+            fuzzy: false,
+            sort: SortKey::default(),
+            order: SortOrder::default(),
+            limit: DEFAULT_LIMIT,
+            mode: SubMode::default(),
+            style: Style::default(),
+            offline: OfflineAction::default(),
+            server_messages: HashMap::new(),
+        };
 
         let content = format!(
-            "Server listing with filter '{}' is being prepared...\n\n\
+            "Server listing `{}` with filter '{}' is being prepared...\n\n\
              Server details will be continuously updated in this message (usually within one minute)\n\n\
-             To stop receiving updates, delete this message or type `!dcsbot unsubscribe`", 
-            filter);
+             To stop receiving updates, delete this message or type `!dcsbot unsubscribe {}`", 
+            sub.name, sub.describe(), sub.name);
 
         // Post the message to the channel, then store its message_id so future updates
         // will edit this message, otherwise fail
         match ChannelId(channel_id).say(http, content.clone()).await {
-            Ok(message) => { 
-                let sub = Sub {
-                    message_id: message.id.0,
-                    filter,
-                    last_content: content,
-                };
-                self.channels.insert(channel_id, sub);
-            },
+            Ok(message) => {
+                sub.message_id = message.id.0;
+                sub.last_content = content;
+                self.config
+                    .channels
+                    .entry(channel_id)
+                    .or_default()
+                    .push(sub);
+            }
             Err(err) => println!("Error sending setup message: {:?}", err),
         }
     }
 
     /**
-     * Unsubscribes from a channel - will attempt to delete the status message we had
+     * Unsubscribes one of a channel's subscriptions, as asked for by a user
+     */
+    async fn unsubscribe_channel(&mut self, http: &Http, channel_id: u64, name: Option<String>) {
+        match self.find_sub(channel_id, name.as_deref()) {
+            Ok(index) => {
+                let name = self.config.channels[&channel_id][index].name.clone();
+                self.remove_sub(http, channel_id, &name).await;
+            }
+            Err(reply) => {
+                let _ = ChannelId(channel_id).say(http, reply).await;
+            }
+        }
+    }
+
+    /**
+     * Removes a subscription - will attempt to delete the status message we had
      * in that channel
      */
-    async fn unsubscribe_channel(&mut self, http: &Http, channel_id: u64) {
-        println!("\x1b[32mUnsubscribing from channel {}\x1b[0m", channel_id);
-        if !self.channels.contains_key(&channel_id) {
-            return;
+    async fn remove_sub(&mut self, http: &Http, channel_id: u64, name: &str) {
+        let subs = match self.config.channels.get_mut(&channel_id) {
+            Some(subs) => subs,
+            None => return,
+        };
+        let index = match subs.iter().position(|sub| sub.name == name) {
+            Some(index) => index,
+            None => return,
+        };
+
+        println!(
+            "\x1b[32mUnsubscribing '{}' from channel {}\x1b[0m",
+            name, channel_id
+        );
+        let sub = subs.remove(index);
+        if subs.is_empty() {
+            self.config.channels.remove(&channel_id);
         }
+        let server_messages = sub
+            .server_messages
+            .values()
+            .map(|message| message.message_id);
+        for message_id in std::iter::once(sub.message_id)
+            .chain(sub.page_ids)
+            .chain(server_messages)
+        {
+            let _ = ChannelId(channel_id).delete_message(http, message_id).await;
+        }
+    }
 
-        let message_id = self.channels.get(&channel_id).unwrap().message_id;
-        let _ = ChannelId(channel_id).delete_message(http, message_id).await;
-        self.channels.remove(&channel_id);
+    /**
+     * Lists a channel's subscriptions, with links to their messages
+     */
+    async fn list_subscriptions(&self, http: &Http, channel_id: u64, guild_id: u64) {
+        let subs = self
+            .config
+            .channels
+            .get(&channel_id)
+            .map_or(&[][..], Vec::as_slice);
+        let mut output = String::new();
+        for sub in subs {
+            let mut options = vec![
+                format!("sorted by {} {}", sub.sort, sub.order),
+                format!("up to {}", sub.limit),
+            ];
+            if sub.mode == SubMode::Servers {
+                options.push("a message per server".to_string());
+            }
+            match sub.style {
+                Style::Markdown => {}
+                Style::Embeds => options.push("embeds".to_string()),
+                Style::Fields => options.push("fields".to_string()),
+            }
+            if sub.chart {
+                options.push("chart".to_string());
+            }
+            if sub.fuzzy {
+                options.push("fuzzy".to_string());
+            }
+            output.push_str(&format!(
+                "`{}`: {} ({}) https://discord.com/channels/{}/{}/{}\n",
+                sub.name,
+                sub.describe(),
+                options.join(", "),
+                guild_id,
+                channel_id,
+                sub.message_id
+            ));
+        }
+        if output.is_empty() {
+            output = "This channel has no subscriptions".to_string();
+        }
+        let _ = ChannelId(channel_id).say(http, output).await;
     }
 
     /**
-     * Handles errors received while broadcasting messages - if the message failed
-     * because the original message or channel are no-longer accessible, it will
-     * append the channel_id to the unsubscribe list
+     * Handles errors received while broadcasting messages - logs them, and
+     * works out whether the message failed because the message or channel are
+     * no-longer accessible
      */
     fn handle_broadcast_error(
         &self,
         err: serenity::Error,
         message_id: u64,
         channel_id: u64,
-        unsubscribe_list: &mut Vec<u64>,
-    ) {
+    ) -> Option<Missing> {
         // Do this here so it's before the err borrow
         let error_text = format!(
             "\x1b[31mError editing message {} in channel {}: {:?}\x1b[0m",
@@ -204,16 +577,14 @@ impl Bot {
                             "\x1b[31mBroadcast Error: Message {} not found in channel {}\x1b[0m",
                             message_id, channel_id
                         );
-                        unsubscribe_list.push(channel_id);
-                        return;
+                        return Some(Missing::Message);
                     }
                     10003 | 50001 => {
                         println!(
                             "\x1b[31mBroadcast Error: Channel {} not found\x1b[0m",
                             channel_id
                         );
-                        unsubscribe_list.push(channel_id);
-                        return;
+                        return Some(Missing::Channel);
                     }
                     _ => {}
                 }
@@ -221,7 +592,7 @@ impl Bot {
         }
 
         println!("{}", error_text);
-        return;
+        None
     }
 
     /**
@@ -232,12 +603,31 @@ impl Bot {
      * TODO: Consider messaging server owner on unsubscribe
      */
     async fn broadcast_servers(&mut self, http: &Http, servers: &Servers) -> Result<()> {
-        println!("Broadcasting servers ({} total)", servers.SERVERS.len());
-        let mut unsubscribe_list = Vec::<u64>::new();
+        println!(
+            "Broadcasting servers ({} total, {} rejected)",
+            servers.SERVERS.len(),
+            servers.rejected
+        );
+        let mut unsubscribe_list = Vec::<(u64, Option<String>)>::new();
+        let mut messages_changed = false;
 
-        for (channel_id, sub) in self.channels.clone().iter_mut() {
-            // Get the text we went to send for this channel
-            let (num, content) = self.render_servers(&servers, &sub.filter);
+        let channels = self.config.channels.clone();
+        let subs = channels
+            .iter()
+            .flat_map(|(channel_id, subs)| subs.iter().map(move |sub| (channel_id, sub.clone())));
+        for (channel_id, mut sub) in subs {
+            // Get the text we went to send for this subscription; with a
+            // message per server, its own message heads theirs
+            let (num, pages, listed) = match sub.mode {
+                SubMode::Listing => {
+                    let (num, pages) = self.render_servers(servers, &sub);
+                    (num, pages, vec![])
+                }
+                SubMode::Servers => {
+                    let (num, listed, header) = self.render_server_messages(servers, &sub);
+                    (num, vec![Page::text(header)], listed)
+                }
+            };
 
             // If it's the same as last time, abort
             // TODO: consider sending anyway after N minutes so the edited time
@@ -246,83 +636,1005 @@ impl Bot {
             //    continue;
             // }
 
-            if content.is_empty() {
-                println!("- No servers found for filter '{}'", sub.filter);
-                continue;
-            } else {
-                println!("- {} servers found for filter '{}'", num, sub.filter);
+            println!("- {} servers found for filter '{}'", num, sub.describe());
+
+            // Once a day, attach a fresh chart (or take it off, if turned off)
+            let now = chrono::Utc::now().timestamp();
+            let mut chart = None;
+            let update_chart = now - sub.chart_updated >= 60 * 60 * 24;
+            if update_chart && sub.chart {
+                let matching = self
+                    .matching_servers(servers, &sub)
+                    .into_iter()
+                    .map(|server| (server.id.clone(), server.NAME.clone()))
+                    .collect();
+                let title = format!("Players on '{}', last 24 hours (UTC)", sub.describe());
+                chart = self.player_chart(matching, 60 * 60 * 24, &title).await;
             }
+            // With the chart off, there's only something to do if one's still attached
+            let remove_chart = update_chart && !sub.chart && sub.chart_attachment.is_some();
+            let replace_chart = chart.is_some() || remove_chart;
 
             // Send the message and handle any errors; if the message is not found,
             // add it to the unsubscribe list
             let res = ChannelId(*channel_id)
-                .edit_message(http, sub.message_id, |m| m.content(content.clone()))
+                .edit_message(http, sub.message_id, |m| {
+                    m.content(&pages[0].content);
+                    m.set_embeds(pages[0].build_embeds(now));
+                    if replace_chart {
+                        if let Some(attachment) = sub.chart_attachment {
+                            m.remove_existing_attachment(AttachmentId(attachment));
+                        }
+                    }
+                    if let Some(png) = chart {
+                        m.attachment(AttachmentType::Bytes {
+                            data: png.into(),
+                            filename: "players.png".to_string(),
+                        });
+                    }
+                    m
+                })
                 .await;
 
             match res {
-                Ok(_) => sub.last_content = content,
-                Err(err) => self.handle_broadcast_error(
-                    err,
-                    sub.message_id,
-                    *channel_id,
-                    &mut unsubscribe_list,
-                ),
+                Ok(message) => {
+                    sub.last_content = pages.iter().map(|page| page.content.as_str()).collect();
+                    if replace_chart {
+                        sub.chart_attachment = message.attachments.first().map(|a| a.id.0);
+                        sub.chart_updated = now;
+                        messages_changed = true;
+                    }
+                    let changed = match sub.mode {
+                        SubMode::Listing => {
+                            self.update_pages(http, *channel_id, &mut sub, &pages[1..])
+                                .await
+                        }
+                        SubMode::Servers => {
+                            self.update_server_messages(
+                                http,
+                                *channel_id,
+                                &mut sub,
+                                servers,
+                                &listed,
+                                &mut unsubscribe_list,
+                            )
+                            .await
+                        }
+                    };
+                    messages_changed |= changed;
+                    let stored =
+                        self.config.channels.get_mut(channel_id).and_then(|subs| {
+                            subs.iter_mut().find(|stored| stored.name == sub.name)
+                        });
+                    if let Some(stored) = stored {
+                        *stored = sub.clone();
+                    }
+                }
+                Err(err) => match self.handle_broadcast_error(err, sub.message_id, *channel_id) {
+                    Some(Missing::Message) => {
+                        unsubscribe_list.push((*channel_id, Some(sub.name.clone())))
+                    }
+                    Some(Missing::Channel) => unsubscribe_list.push((*channel_id, None)),
+                    None => {}
+                },
             }
         }
 
         // Unsubscribe from any channels where we couldn't find the message
-        if !unsubscribe_list.is_empty() {
-            for channel_id in &unsubscribe_list {
-                self.unsubscribe_channel(http, *channel_id).await;
+        for (channel_id, name) in &unsubscribe_list {
+            match name {
+                Some(name) => self.remove_sub(http, *channel_id, name).await,
+                None => {
+                    println!("\x1b[32mUnsubscribing from channel {}\x1b[0m", channel_id);
+                    self.config.channels.remove(channel_id);
+                }
             }
+        }
+        if !unsubscribe_list.is_empty() || messages_changed {
             let _ = self.save_channels().await;
         }
 
         Ok(())
     }
 
+    /**
+     * Brings a subscription's messages after its first in line with the rest
+     * of its listing: editing the ones it has, posting more as the listing
+     * grows and deleting those it no longer needs. Returns whether the set of
+     * messages changed
+     */
+    async fn update_pages(
+        &self,
+        http: &Http,
+        channel_id: u64,
+        sub: &mut Sub,
+        pages: &[Page],
+    ) -> bool {
+        let channel = ChannelId(channel_id);
+        let now = chrono::Utc::now().timestamp();
+        let mut page_ids = vec![];
+        let mut stale = vec![];
+
+        for (index, page) in pages.iter().enumerate() {
+            if let Some(message_id) = sub.page_ids.get(index) {
+                let res = channel
+                    .edit_message(http, *message_id, |m| {
+                        m.content(&page.content).set_embeds(page.build_embeds(now))
+                    })
+                    .await;
+                match res {
+                    Ok(_) => {
+                        page_ids.push(*message_id);
+                        continue;
+                    }
+                    Err(err) => {
+                        // Most likely deleted; post it again
+                        println!(
+                            "\x1b[31mError editing message {} in channel {}: {:?}\x1b[0m",
+                            message_id, channel_id, err
+                        );
+                        stale.push(*message_id);
+                    }
+                }
+            }
+            let res = channel
+                .send_message(http, |m| {
+                    m.content(&page.content).set_embeds(page.build_embeds(now))
+                })
+                .await;
+            match res {
+                Ok(message) => page_ids.push(message.id.0),
+                Err(err) => println!("\x1b[31mError sending page: {:?}\x1b[0m", err),
+            }
+        }
+
+        stale.extend(sub.page_ids.iter().skip(pages.len()));
+        for message_id in &stale {
+            let _ = channel.delete_message(http, *message_id).await;
+        }
+
+        let changed = page_ids != sub.page_ids;
+        sub.page_ids = page_ids;
+        changed
+    }
+
+    /**
+     * Keeps a message per server up to date for a subscription: editing each
+     * listed server's message when it changes, posting one for each server
+     * newly listed, and deleting (or marking offline) those of servers that
+     * have gone. Returns whether the set of messages changed
+     */
+    async fn update_server_messages(
+        &self,
+        http: &Http,
+        channel_id: u64,
+        sub: &mut Sub,
+        servers: &Servers,
+        listed: &[&Server],
+        unsubscribe_list: &mut Vec<(u64, Option<String>)>,
+    ) -> bool {
+        let channel = ChannelId(channel_id);
+        let now = chrono::Utc::now().timestamp();
+        let mut changed = false;
+
+        for server in listed {
+            let content = match sub.style {
+                Style::Markdown => Page::text(self.render_server(server)),
+                Style::Embeds | Style::Fields => Page {
+                    content: String::new(),
+                    embeds: vec![self.server_embed(server)],
+                },
+            };
+            if let Some(message) = sub.server_messages.get_mut(&server.id) {
+                if message.content == content && message.offline_since.is_none() {
+                    continue;
+                }
+                let res = channel
+                    .edit_message(http, message.message_id, |m| {
+                        m.content(&content.content)
+                            .set_embeds(content.build_embeds(now))
+                    })
+                    .await;
+                match res {
+                    Ok(_) => {
+                        message.name = normalize::display_name(&server.NAME);
+                        message.content = content;
+                        changed |= message.offline_since.take().is_some();
+                        continue;
+                    }
+                    Err(err) => {
+                        match self.handle_broadcast_error(err, message.message_id, channel_id) {
+                            // Someone deleted it; post it again
+                            Some(Missing::Message) => {
+                                sub.server_messages.remove(&server.id);
+                                changed = true;
+                            }
+                            Some(Missing::Channel) => {
+                                unsubscribe_list.push((channel_id, None));
+                                return changed;
+                            }
+                            None => continue,
+                        }
+                    }
+                }
+            }
+
+            let res = channel
+                .send_message(http, |m| {
+                    m.content(&content.content)
+                        .set_embeds(content.build_embeds(now))
+                })
+                .await;
+            match res {
+                Ok(message) => {
+                    let message = ServerMessage {
+                        message_id: message.id.0,
+                        name: normalize::display_name(&server.NAME),
+                        offline_since: None,
+                        content,
+                    };
+                    sub.server_messages.insert(server.id.clone(), message);
+                    changed = true;
+                }
+                Err(err) => println!("\x1b[31mError sending server message: {:?}\x1b[0m", err),
+            }
+        }
+
+        // Servers that are no longer listed: those still online just don't
+        // match any more, the rest have gone offline
+        let online: HashSet<&ServerId> = servers.SERVERS.iter().map(|server| &server.id).collect();
+        let listed: HashSet<&ServerId> = listed.iter().map(|server| &server.id).collect();
+        let gone: Vec<ServerId> = sub
+            .server_messages
+            .keys()
+            .filter(|id| !listed.contains(id))
+            .cloned()
+            .collect();
+        for id in gone {
+            let message = sub.server_messages.get_mut(&id).unwrap();
+            let keep = sub.offline == OfflineAction::Mark
+                && !online.contains(&id)
+                && message
+                    .offline_since
+                    .is_none_or(|since| now - since < 60 * 60 * 24);
+            if !keep {
+                let _ = channel.delete_message(http, message.message_id).await;
+                sub.server_messages.remove(&id);
+                changed = true;
+            } else if message.offline_since.is_none() {
+                let content = format!("~~**{}**~~\nOffline since <t:{}:R>", message.name, now);
+                let _ = channel
+                    .edit_message(http, message.message_id, |m| {
+                        m.content(content).set_embeds(vec![])
+                    })
+                    .await;
+                message.offline_since = Some(now);
+                changed = true;
+            }
+        }
+        changed
+    }
+
     /**
      * Load stored channel subscriptions from our file on disk
      */
     fn load_channels(&mut self) -> Result<()> {
         println!("Loading channels");
-        let file = OpenOptions::new()
-            .read(true)
-            .open(self.config_path.clone())?;
-        let reader = BufReader::new(file);
-
-        self.channels = serde_json::from_reader(reader)?;
-        println!("{} channels loaded", self.channels.len());
+        self.config = Config::load(&self.config_path)?;
+        println!(
+            "{} channels loaded ({} subscriptions), {} release channels",
+            self.config.channels.len(),
+            self.config.channels.values().map(Vec::len).sum::<usize>(),
+            self.config.releases.len()
+        );
         Ok(())
     }
 
     /**
-     * Load stored channel subscriptions from our file on disk
-     * TODO: This might block the rest of the app; consider whether this
-     * should run in its own watch-channel-powered thread
+     * Save channel subscriptions to our file on disk
      */
     async fn save_channels(&self) -> Result<()> {
         println!("Saving channels");
-        let file = OpenOptions::new()
-            .truncate(true)
-            .write(true)
-            .create(true)
-            .open(self.config_path.clone())
-            .unwrap();
-        serde_json::to_writer(file, &self.channels)?;
-        Ok(())
+        self.config.save(&self.config_path)
+    }
+
+    /**
+     * Update the known version strings for the newest Open Beta and Stable
+     * releases; this is used in format_version
+     */
+    fn set_versions(&mut self, changelog: &Changelog) {
+        self.versions.clear();
+        for branch in [Branch::OpenBeta, Branch::Stable] {
+            if let Some(release) = changelog.latest(branch) {
+                println!("Updating versions. {}: {}", branch, release.version);
+                self.versions.insert(release.version.clone(), branch);
+            }
+        }
     }
 
     /**
-     * Update the known version strings for Open Beta and Stable; this is used in
-     * format_version
+     * Starts or stops posting release announcements to a channel, optionally
+     * pinging a role
      */
-    fn set_versions(&mut self, beta: String, stable: String) {
-        println!("Updating versions. beta: {}, stable: {}", beta, stable);
-        self.versions = HashMap::from([
-            (beta.clone(), format!("Open Beta ({})", beta)),
-            (stable.clone(), format!("Stable ({})", stable)),
-        ]);
+    async fn subscribe_releases(&mut self, http: &Http, channel_id: u64, role_id: Option<u64>) {
+        println!(
+            "\x1b[32mSubscribing to releases in channel {}\x1b[0m",
+            channel_id
+        );
+        self.config
+            .releases
+            .insert(channel_id, ReleaseSub { role_id });
+
+        let mut content =
+            String::from("New DCS Open Beta and Stable releases will be announced in this channel");
+        if let Some(role_id) = role_id {
+            content += &format!(", pinging <@&{}>", role_id);
+        }
+        content += ". To stop, type `!dcsbot releases off`";
+        let _ = ChannelId(channel_id).say(http, content).await;
+    }
+
+    async fn unsubscribe_releases(&mut self, http: &Http, channel_id: u64) {
+        println!(
+            "\x1b[32mUnsubscribing from releases in channel {}\x1b[0m",
+            channel_id
+        );
+        if self.config.releases.remove(&channel_id).is_some() {
+            let _ = ChannelId(channel_id)
+                .say(http, "Release announcements stopped")
+                .await;
+        }
+    }
+
+    fn format_release(&self, release: &Release, role_id: Option<u64>) -> String {
+        let mut content = String::new();
+        if let Some(role_id) = role_id {
+            content += &format!("<@&{}> ", role_id);
+        }
+        content += &format!(
+            "**New DCS {} release: {}**",
+            release.branch, release.version
+        );
+        if let Some(date) = release.date {
+            content += &format!(" ({})", date.format("%-d %B %Y"));
+        }
+        content += &format!("\n{}\n<{}>", release.headline, release.link);
+        content
+    }
+
+    /**
+     * Posts the newest release on each branch to subscribed channels if we
+     * haven't announced it before. The first time we see a branch we just note
+     * its newest release, so a fresh install doesn't announce old news
+     */
+    async fn announce_releases(&mut self, http: &Http, changelog: &Changelog) {
+        let mut changed = false;
+
+        for branch in [Branch::OpenBeta, Branch::Stable] {
+            let release = match changelog.latest(branch) {
+                Some(release) => release,
+                None => continue,
+            };
+
+            match self.config.announced.get(&branch) {
+                Some(announced) if *announced >= release.version => continue,
+                Some(_) => {
+                    println!("\x1b[32mAnnouncing {} {}\x1b[0m", branch, release.version);
+                    let mut sent = 0;
+                    for (channel_id, release_sub) in &self.config.releases {
+                        let content = self.format_release(release, release_sub.role_id);
+                        match ChannelId(*channel_id).say(http, content).await {
+                            Ok(_) => sent += 1,
+                            Err(err) => println!(
+                                "\x1b[31mError announcing release in channel {}: {:?}\x1b[0m",
+                                channel_id, err
+                            ),
+                        }
+                    }
+
+                    // If Discord couldn't take it anywhere, try again next time
+                    if sent == 0 && !self.config.releases.is_empty() {
+                        continue;
+                    }
+                }
+                None => println!("First sighting of {}: {}", branch, release.version),
+            }
+
+            self.config
+                .announced
+                .insert(branch, release.version.clone());
+            changed = true;
+        }
+
+        if changed {
+            let _ = self.save_channels().await;
+        }
+    }
+
+    /**
+     * Remembers when servers last changed, for sorting listings by it
+     */
+    fn note_changes(&mut self, events: &[ServerEvent]) {
+        let now = chrono::Utc::now().timestamp();
+        for event in events {
+            match event {
+                ServerEvent::Offline(server) => {
+                    self.changed.remove(&server.id);
+                }
+                event => {
+                    self.changed.insert(event.id().clone(), now);
+                }
+            }
+        }
+    }
+
+    /**
+     * Logs what changed since the last poll; everyday player count changes
+     * are only counted
+     */
+    fn log_events(&self, events: &[ServerEvent]) {
+        let mut player_changes = 0;
+        for event in events {
+            match event {
+                ServerEvent::PlayersChanged { .. } => player_changes += 1,
+                event => println!("- {}", event),
+            }
+        }
+        println!(
+            "{} events, {} player count changes",
+            events.len(),
+            player_changes
+        );
+    }
+
+    fn format_stats(&self, stats: &ServerStats) -> String {
+        let mut text = format!(
+            "**{}**\nPeak {} players, average {:.1}, online {:.1} hours\n",
+            normalize::display_name(&stats.name),
+            stats.peak,
+            stats.average,
+            stats.online.as_secs_f64() / 3600.0,
+        );
+
+        if !stats.missions.is_empty() {
+            let missions: Vec<String> = stats
+                .missions
+                .iter()
+                .map(|(mission, hours)| {
+                    format!("{} ({}h)", normalize::display_name(mission), hours)
+                })
+                .collect();
+            text += &format!("Missions: {}\n", missions.join(", "));
+        }
+
+        if !stats.busiest_hours.is_empty() {
+            let hours: Vec<String> = stats
+                .busiest_hours
+                .iter()
+                .map(|(hour, average)| format!("{:02}:00 ({:.1})", hour, average))
+                .collect();
+            text += &format!("Busiest hours (UTC): {}\n", hours.join(", "));
+        }
+        text + "\n"
+    }
+
+    /**
+     * Replies with player statistics for the servers matching the filter,
+     * busiest first
+     */
+    async fn send_history(&self, http: &Http, channel_id: u64, filter: String, days: u32) {
+        const MAX_SERVERS: usize = 5;
+        let channel = ChannelId(channel_id);
+
+        let history = match &self.history {
+            Some(history) => history,
+            None => {
+                let _ = channel.say(http, "History isn't being recorded").await;
+                return;
+            }
+        };
+
+        let days = days.clamp(1, 365);
+        let since = chrono::Utc::now().timestamp() - days as i64 * 60 * 60 * 24;
+
+        let result = match history_matches(history, &filter).await {
+            Ok(matching) => history.stats(matching, since).await,
+            Err(err) => Err(err),
+        };
+
+        let mut stats = match result {
+            Ok(stats) => stats,
+            Err(err) => {
+                println!("\x1b[31mError reading history: {:?}\x1b[0m", err);
+                let _ = channel
+                    .say(http, "Sorry, history isn't available right now")
+                    .await;
+                return;
+            }
+        };
+        stats.retain(|stats| stats.online > Duration::ZERO || stats.peak > 0);
+
+        if stats.is_empty() {
+            let _ = channel
+                .say(
+                    http,
+                    format!("No history for '{}' in the last {} days", filter, days),
+                )
+                .await;
+            return;
+        }
+
+        stats.sort_by(|a, b| b.peak.cmp(&a.peak).then(b.average.total_cmp(&a.average)));
+
+        let mut content = format!("History for '{}' over the last {} days\n\n", filter, days);
+        for server in stats.iter().take(MAX_SERVERS) {
+            content += &self.format_stats(server);
+        }
+        if stats.len() > MAX_SERVERS {
+            content += &format!("+{} more servers", stats.len() - MAX_SERVERS);
+        }
+
+        if let Err(err) = channel.say(http, content).await {
+            println!("Error sending history: {:?}", err);
+        }
+    }
+
+    /**
+     * Draws a chart of player counts over the last `range` seconds for up to
+     * five servers, the busiest first. None if there's no history to draw
+     */
+    async fn player_chart(
+        &self,
+        servers: Vec<(ServerId, String)>,
+        range: i64,
+        title: &str,
+    ) -> Option<Vec<u8>> {
+        const MAX_SERVERS: usize = 5;
+        let history = self.history.as_ref()?;
+
+        let to = chrono::Utc::now().timestamp();
+        let from = to - range;
+        // About two points per pixel; no finer than our samples
+        let bucket = (range / 1500).max(60);
+        let gap = 3 * bucket.max(history.sample_interval().as_secs() as i64);
+
+        let mut players = match history.players(servers, from, bucket).await {
+            Ok(players) => players,
+            Err(err) => {
+                println!("\x1b[31mError reading history: {:?}\x1b[0m", err);
+                return None;
+            }
+        };
+        players.retain(|(_, points)| !points.is_empty());
+        if players.is_empty() {
+            return None;
+        }
+
+        let peak =
+            |points: &[(i64, f64)]| points.iter().map(|(_, value)| *value).fold(0.0, f64::max);
+        players.sort_by(|a, b| peak(&b.1).total_cmp(&peak(&a.1)));
+
+        let series: Vec<Series> = players
+            .into_iter()
+            .take(MAX_SERVERS)
+            .map(|(name, points)| Series {
+                name: normalize::display_name(&name),
+                points,
+            })
+            .collect();
+
+        match line_chart(title, &series, from, to, gap) {
+            Ok(png) => Some(png),
+            Err(err) => {
+                println!("\x1b[31mError drawing chart: {}\x1b[0m", err);
+                None
+            }
+        }
+    }
+
+    /**
+     * Replies with a chart of player counts for the servers matching the filter
+     */
+    async fn send_chart(&self, http: &Http, channel_id: u64, filter: String, range: Duration) {
+        let channel = ChannelId(channel_id);
+
+        let history = match &self.history {
+            Some(history) => history,
+            None => {
+                let _ = channel.say(http, "History isn't being recorded").await;
+                return;
+            }
+        };
+
+        let matching = match history_matches(history, &filter).await {
+            Ok(matching) => matching,
+            Err(err) => {
+                println!("\x1b[31mError reading history: {:?}\x1b[0m", err);
+                let _ = channel
+                    .say(http, "Sorry, history isn't available right now")
+                    .await;
+                return;
+            }
+        };
+
+        let hours = range.as_secs() / 3600;
+        let span = match hours % 24 {
+            0 if hours > 24 => format!("{} days", hours / 24),
+            _ => format!("{} hours", hours),
+        };
+        let title = format!("Players on '{}', last {} (UTC)", filter, span);
+
+        let png = match self
+            .player_chart(matching, range.as_secs() as i64, &title)
+            .await
+        {
+            Some(png) => png,
+            None => {
+                let _ = channel
+                    .say(
+                        http,
+                        format!("No history for '{}' in the last {}", filter, span),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        let file = AttachmentType::Bytes {
+            data: png.into(),
+            filename: "players.png".to_string(),
+        };
+        if let Err(err) = channel.send_files(http, vec![file], |m| m).await {
+            println!("Error sending chart: {:?}", err);
+        }
+    }
+
+    /**
+     * Turns the daily chart on a subscription's message on or off
+     */
+    async fn set_chart(&mut self, http: &Http, channel_id: u64, name: Option<String>, chart: bool) {
+        let channel = ChannelId(channel_id);
+        let has_history = self.history.is_some();
+        let sub = match self.sub_to_set(http, channel_id, name.as_deref()).await {
+            Some(sub) => sub,
+            None => return,
+        };
+
+        sub.chart = chart;
+        sub.chart_updated = 0; // so it's added or removed on the next update
+
+        let reply = match (chart, has_history) {
+            (true, true) => "A daily player chart will be added to the server listing",
+            (true, false) => "History isn't being recorded, so there is no chart to add",
+            (false, _) => "The daily player chart will be removed",
+        };
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * Turns fuzzy matching on or off for a subscription
+     */
+    async fn set_fuzzy(&mut self, http: &Http, channel_id: u64, name: Option<String>, fuzzy: bool) {
+        let channel = ChannelId(channel_id);
+        let sub = match self.sub_to_set(http, channel_id, name.as_deref()).await {
+            Some(sub) => sub,
+            None => return,
+        };
+
+        sub.fuzzy = fuzzy;
+        let reply = if fuzzy {
+            "The filter will now also match servers with similar names, allowing for typos"
+        } else {
+            "The filter will only match servers containing its text"
+        };
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * Changes what a subscription's listing is sorted by
+     */
+    async fn set_sort(
+        &mut self,
+        http: &Http,
+        channel_id: u64,
+        name: Option<String>,
+        key: SortKey,
+        order: SortOrder,
+    ) {
+        let channel = ChannelId(channel_id);
+        let sub = match self.sub_to_set(http, channel_id, name.as_deref()).await {
+            Some(sub) => sub,
+            None => return,
+        };
+
+        sub.sort = key;
+        sub.order = order;
+        let reply = format!("Servers will be sorted by {} ({})", key, order);
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * Changes how many servers a subscription's listing shows
+     */
+    async fn set_limit(
+        &mut self,
+        http: &Http,
+        channel_id: u64,
+        name: Option<String>,
+        limit: usize,
+    ) {
+        let channel = ChannelId(channel_id);
+        if limit == 0 || limit > MAX_LIMIT {
+            let reply = format!("The limit has to be between 1 and {}", MAX_LIMIT);
+            let _ = channel.say(http, reply).await;
+            return;
+        }
+        let sub = match self.sub_to_set(http, channel_id, name.as_deref()).await {
+            Some(sub) => sub,
+            None => return,
+        };
+
+        if sub.mode == SubMode::Servers && limit > MAX_SERVER_MESSAGES {
+            let reply = format!(
+                "With a message per server, the limit has to be between 1 and {}",
+                MAX_SERVER_MESSAGES
+            );
+            let _ = channel.say(http, reply).await;
+            return;
+        }
+
+        sub.limit = limit;
+        let reply = format!("Up to {} servers will be listed", limit);
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * Switches a subscription between one listing and a message per server,
+     * deleting the messages the old mode used
+     */
+    async fn set_mode(
+        &mut self,
+        http: &Http,
+        channel_id: u64,
+        name: Option<String>,
+        mode: SubMode,
+    ) {
+        let channel = ChannelId(channel_id);
+        let sub = match self.sub_to_set(http, channel_id, name.as_deref()).await {
+            Some(sub) => sub,
+            None => return,
+        };
+
+        if sub.mode != mode {
+            let page_ids = std::mem::take(&mut sub.page_ids);
+            let server_messages = std::mem::take(&mut sub.server_messages);
+            let message_ids = page_ids
+                .into_iter()
+                .chain(server_messages.values().map(|message| message.message_id));
+            for message_id in message_ids {
+                let _ = channel.delete_message(http, message_id).await;
+            }
+            sub.mode = mode;
+        }
+
+        let reply = match mode {
+            SubMode::Listing => "Servers will be listed together".to_string(),
+            SubMode::Servers if sub.limit > MAX_SERVER_MESSAGES => {
+                sub.limit = MAX_SERVER_MESSAGES;
+                format!(
+                    "Each server will get a message of its own, for up to {} servers",
+                    MAX_SERVER_MESSAGES
+                )
+            }
+            SubMode::Servers => "Each server will get a message of its own".to_string(),
+        };
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * Changes how a subscription draws its servers: as markdown, an embed per
+     * server, or an embed with a field per server
+     */
+    async fn set_style(
+        &mut self,
+        http: &Http,
+        channel_id: u64,
+        name: Option<String>,
+        style: Style,
+    ) {
+        let channel = ChannelId(channel_id);
+        let sub = match self.sub_to_set(http, channel_id, name.as_deref()).await {
+            Some(sub) => sub,
+            None => return,
+        };
+
+        sub.style = style;
+        let reply = match style {
+            Style::Markdown => "Servers will be listed as text",
+            Style::Embeds => "Each server will be shown in an embed of its own",
+            Style::Fields => "Servers will be listed in an embed, a field each",
+        };
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * Changes whether a subscription deletes the messages of servers that go
+     * offline, or marks them as offline
+     */
+    async fn set_offline(
+        &mut self,
+        http: &Http,
+        channel_id: u64,
+        name: Option<String>,
+        offline: OfflineAction,
+    ) {
+        let channel = ChannelId(channel_id);
+        let sub = match self.sub_to_set(http, channel_id, name.as_deref()).await {
+            Some(sub) => sub,
+            None => return,
+        };
+
+        sub.offline = offline;
+        let reply = match offline {
+            OfflineAction::Delete => "Messages of servers that go offline will be deleted",
+            OfflineAction::Mark => {
+                "Messages of servers that go offline will say so, and be deleted after a day"
+            }
+        };
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * The timezone a guild has asked for, or UTC
+     */
+    fn timezone(&self, guild_id: u64) -> Tz {
+        self.config
+            .timezones
+            .get(&guild_id)
+            .and_then(|name| name.parse().ok())
+            .unwrap_or(Tz::UTC)
+    }
+
+    async fn set_timezone(&mut self, http: &Http, channel_id: u64, guild_id: u64, name: String) {
+        let channel = ChannelId(channel_id);
+        let reply = match name.parse::<Tz>() {
+            Ok(timezone) => {
+                self.config
+                    .timezones
+                    .insert(guild_id, timezone.name().to_string());
+                format!("Times will be shown in {}", timezone.name())
+            }
+            Err(_) => format!(
+                "Unknown timezone '{}'; use a name like `Europe/London` or `America/New_York`",
+                name
+            ),
+        };
+        let _ = channel.say(http, reply).await;
+    }
+
+    /**
+     * Writes a heatmap as rows of coloured squares, Monday to Sunday
+     */
+    fn format_heatmap(&self, grid: &[[Option<f64>; 24]; 7]) -> String {
+        const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+        const SQUARES: [&str; 5] = [
+            "\u{1f7e6}",
+            "\u{1f7e9}",
+            "\u{1f7e8}",
+            "\u{1f7e7}",
+            "\u{1f7e5}",
+        ];
+
+        let max = grid
+            .iter()
+            .flatten()
+            .flatten()
+            .fold(1.0, |max: f64, v| max.max(*v));
+        let mut text = String::new();
+        for (day, row) in grid.iter().enumerate() {
+            text += WEEKDAYS[day];
+            text += " ";
+            for value in row {
+                text += match value {
+                    None => "\u{2b1b}",
+                    Some(value) if *value < 0.5 => "\u{2b1c}",
+                    Some(value) => {
+                        let index = (value / max * SQUARES.len() as f64) as usize;
+                        SQUARES[index.min(SQUARES.len() - 1)]
+                    }
+                };
+            }
+            text += "\n";
+        }
+        text
+    }
+
+    /**
+     * Replies with a weekday by hour heatmap of average players on the servers
+     * matching the filter, in the guild's timezone
+     */
+    async fn send_heatmap(
+        &self,
+        http: &Http,
+        channel_id: u64,
+        guild_id: u64,
+        filter: String,
+        days: u32,
+    ) {
+        let channel = ChannelId(channel_id);
+
+        let history = match &self.history {
+            Some(history) => history,
+            None => {
+                let _ = channel.say(http, "History isn't being recorded").await;
+                return;
+            }
+        };
+
+        let days = days.clamp(1, 365);
+        let since = chrono::Utc::now().timestamp() - days as i64 * 60 * 60 * 24;
+
+        let result: rusqlite::Result<_> = async {
+            let matching = history_matches(history, &filter).await?;
+            let ids = matching.into_iter().map(|(id, _)| id).collect();
+            let hours = history.hourly_players(ids, since).await?;
+            Ok((hours, history.recorded_hours(since).await?))
+        }
+        .await;
+        let (hours, recorded) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                println!("\x1b[31mError reading history: {:?}\x1b[0m", err);
+                let _ = channel
+                    .say(http, "Sorry, history isn't available right now")
+                    .await;
+                return;
+            }
+        };
+        if hours.is_empty() {
+            let _ = channel
+                .say(
+                    http,
+                    format!("No history for '{}' in the last {} days", filter, days),
+                )
+                .await;
+            return;
+        }
+
+        let timezone = self.timezone(guild_id);
+        let grid = weekday_hour_grid(&hours, &recorded, timezone);
+
+        let title = format!(
+            "Average players on '{}', last {} days ({})",
+            filter,
+            days,
+            timezone.name()
+        );
+        let content = format!(
+            "{}\n{}Hours 00-23, \u{2b1c} empty \u{1f7e6}\u{1f7e9}\u{1f7e8}\u{1f7e7}\u{1f7e5} busiest, \u{2b1b} no data",
+            title,
+            self.format_heatmap(&grid)
+        );
+
+        let png = match heatmap(&title, &grid) {
+            Ok(png) => png,
+            Err(err) => {
+                println!("\x1b[31mError drawing heatmap: {}\x1b[0m", err);
+                let _ = channel.say(http, content).await;
+                return;
+            }
+        };
+
+        let file = AttachmentType::Bytes {
+            data: png.into(),
+            filename: "heatmap.png".to_string(),
+        };
+        if let Err(err) = channel
+            .send_files(http, vec![file], |m| m.content(content))
+            .await
+        {
+            println!("Error sending heatmap: {:?}", err);
+        }
     }
 
     /**
@@ -335,21 +1647,84 @@ impl Bot {
                 Some(servers_message) = self.servers_rx.recv() => {
                     match servers_message {
                         ServersMessage::Servers(servers) => {
+                            if let Some(history) = &mut self.history {
+                                history.record(&servers).await;
+                            }
                             let _ = self.broadcast_servers(http, &servers).await;
                         }
-                        ServersMessage::Versions(versions) => {
-                            self.set_versions(versions.0, versions.1)
+                        ServersMessage::Events(events) => {
+                            self.log_events(&events);
+                            self.note_changes(&events);
+                            if let Some(history) = &self.history {
+                                history.record_events(&events).await;
+                            }
+                        }
+                        ServersMessage::Releases(changelog) => {
+                            self.set_versions(&changelog);
+                            self.announce_releases(http, &changelog).await;
                         }
                     }
                 },
                 Some(handler_message) = handler_rx.recv() => {
                     match handler_message {
-                        HandlerMessage::SubscribeChannel(channel_id, filter) => {
-                            self.subscribe_channel(http, channel_id, filter).await;
+                        HandlerMessage::SubscribeChannel(channel_id, name, regex, filter, matcher) => {
+                            self.subscribe_channel(http, channel_id, name, regex, filter, matcher).await;
                             let _ = self.save_channels().await;
                         },
-                        HandlerMessage::UnsubscribeChannel(channel_id) => {
-                            self.unsubscribe_channel(http, channel_id).await;
+                        HandlerMessage::UnsubscribeChannel(channel_id, name) => {
+                            self.unsubscribe_channel(http, channel_id, name).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::ListSubscriptions(channel_id, guild_id) => {
+                            self.list_subscriptions(http, channel_id, guild_id).await;
+                        },
+                        HandlerMessage::SubscribeReleases(channel_id, role_id) => {
+                            self.subscribe_releases(http, channel_id, role_id).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::UnsubscribeReleases(channel_id) => {
+                            self.unsubscribe_releases(http, channel_id).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::History(channel_id, filter, days) => {
+                            self.send_history(http, channel_id, filter, days).await;
+                        },
+                        HandlerMessage::Chart(channel_id, filter, range) => {
+                            self.send_chart(http, channel_id, filter, range).await;
+                        },
+                        HandlerMessage::Heatmap(channel_id, guild_id, filter, days) => {
+                            self.send_heatmap(http, channel_id, guild_id, filter, days).await;
+                        },
+                        HandlerMessage::SetTimezone(channel_id, guild_id, timezone) => {
+                            self.set_timezone(http, channel_id, guild_id, timezone).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::SetChart(channel_id, name, chart) => {
+                            self.set_chart(http, channel_id, name, chart).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::SetFuzzy(channel_id, name, fuzzy) => {
+                            self.set_fuzzy(http, channel_id, name, fuzzy).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::SetSort(channel_id, name, key, order) => {
+                            self.set_sort(http, channel_id, name, key, order).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::SetLimit(channel_id, name, limit) => {
+                            self.set_limit(http, channel_id, name, limit).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::SetMode(channel_id, name, mode) => {
+                            self.set_mode(http, channel_id, name, mode).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::SetStyle(channel_id, name, style) => {
+                            self.set_style(http, channel_id, name, style).await;
+                            let _ = self.save_channels().await;
+                        },
+                        HandlerMessage::SetOffline(channel_id, name, offline) => {
+                            self.set_offline(http, channel_id, name, offline).await;
                             let _ = self.save_channels().await;
                         },
                     }
@@ -391,7 +1766,301 @@ impl Bot {
     }
 }
 
-pub async fn start(token: String, config_path: String, servers_rx: mpsc::Receiver<ServersMessage>) {
-    let mut bot = Bot::new(token, config_path, servers_rx);
+/**
+ * Splits a listing's entries into messages that fit in Discord's limit,
+ * never splitting an entry, up to MAX_PAGES messages. If not every server
+ * fits (or some were left out by the subscription's limit), the last
+ * message says how many more there are
+ */
+fn paginate(entries: Vec<String>, total: usize) -> Vec<String> {
+    // Room for the footer is kept on every page, so it always fits
+    let capacity = MESSAGE_LIMIT - FOOTER_ROOM;
+    let mut pages = vec![String::new()];
+    let mut shown = 0;
+    for entry in entries {
+        // No entry should come near the limit, but be sure
+        let entry = match entry.char_indices().nth(capacity) {
+            Some((index, _)) => entry[..index].to_string(),
+            None => entry,
+        };
+
+        let page = pages.last_mut().unwrap();
+        if !page.is_empty() && page.chars().count() + entry.chars().count() > capacity {
+            if pages.len() == MAX_PAGES {
+                break;
+            }
+            pages.push(String::new());
+        }
+        pages.last_mut().unwrap().push_str(&entry);
+        shown += 1;
+    }
+
+    if let Some(footer) = more_servers(total - shown) {
+        pages.last_mut().unwrap().push_str(&footer);
+    }
+    pages
+}
+
+/**
+ * Splits a listing's embeds into messages as paginate does, up to
+ * MAX_EMBEDS to a message, with the count of servers left out in the last
+ * embed's footer
+ */
+fn paginate_embeds(embeds: Vec<Embed>, total: usize) -> Vec<Page> {
+    let mut pages = vec![Page::default()];
+    let mut shown = 0;
+    for mut embed in embeds {
+        embed.clip(embed::EMBED_LIMIT - FOOTER_ROOM);
+        let page = pages.last_mut().unwrap();
+        let size: usize = page.embeds.iter().map(Embed::size).sum();
+        if page.embeds.len() == embed::MAX_EMBEDS
+            || (!page.embeds.is_empty() && size + embed.size() > embed::EMBED_LIMIT - FOOTER_ROOM)
+        {
+            if pages.len() == MAX_PAGES {
+                break;
+            }
+            pages.push(Page::default());
+        }
+        pages.last_mut().unwrap().embeds.push(embed);
+        shown += 1;
+    }
+
+    if let Some(embed) = pages.last_mut().unwrap().embeds.last_mut() {
+        embed.footer = more_servers(total - shown);
+    }
+    pages
+}
+
+/**
+ * The note at the end of a listing that couldn't show every server
+ */
+fn more_servers(more: usize) -> Option<String> {
+    match more {
+        0 => None,
+        1 => Some("+1 more server".to_string()),
+        more => Some(format!("+{} more servers", more)),
+    }
+}
+
+/**
+ * Averages hourly player counts into a weekday by hour grid in the given
+ * timezone. Hours we recorded anything in but have no count for (nothing
+ * matching was online) count as no players; hours we weren't recording in
+ * are left out, so the bot being down doesn't drag the averages down
+ */
+fn weekday_hour_grid(
+    hours: &HashMap<i64, f64>,
+    recorded: &HashSet<i64>,
+    timezone: Tz,
+) -> [[Option<f64>; 24]; 7] {
+    let mut totals = [[(0.0, 0); 24]; 7];
+    let all: HashSet<i64> = recorded.iter().chain(hours.keys()).copied().collect();
+    for hour in all {
+        if let Some(local) = timezone.timestamp_opt(hour, 0).single() {
+            let cell =
+                &mut totals[local.weekday().num_days_from_monday() as usize][local.hour() as usize];
+            cell.0 += hours.get(&hour).copied().unwrap_or(0.0);
+            cell.1 += 1;
+        }
+    }
+    totals.map(|row| {
+        row.map(|(total, count)| match count {
+            0 => None,
+            count => Some(total / count as f64),
+        })
+    })
+}
+
+/**
+ * The servers in our history whose last known name matches the filter
+ */
+async fn history_matches(
+    history: &History,
+    filter: &str,
+) -> rusqlite::Result<Vec<(ServerId, String)>> {
+    let filter = normalize::fold(filter);
+    let servers = history.servers().await?;
+    Ok(servers
+        .into_iter()
+        .filter(|(_, name)| normalize::fold(&normalize::display_name(name)).contains(&filter))
+        .collect())
+}
+
+pub async fn start(
+    token: String,
+    config_path: String,
+    history: Option<History>,
+    servers_rx: mpsc::Receiver<ServersMessage>,
+) {
+    let mut bot = Bot::new(token, config_path, history, servers_rx);
     bot.start().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lengths(pages: &[String]) -> Vec<usize> {
+        pages.iter().map(|page| page.chars().count()).collect()
+    }
+
+    fn embed(size: usize) -> Embed {
+        Embed {
+            title: "Server".to_string(),
+            description: "x".repeat(size - "Server".len()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fills_messages_up_to_the_limit() {
+        let capacity = MESSAGE_LIMIT - FOOTER_ROOM;
+        let entries = vec!["a".repeat(capacity / 2); 3];
+        let pages = paginate(entries, 3);
+        assert_eq!(lengths(&pages), [capacity, capacity / 2]);
+
+        // One character more doesn't fit
+        let entries = vec!["a".repeat(capacity / 2), "b".repeat(capacity / 2 + 1)];
+        assert_eq!(
+            lengths(&paginate(entries, 2)),
+            [capacity / 2, capacity / 2 + 1]
+        );
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        let entries = vec!["ж".repeat(MESSAGE_LIMIT - FOOTER_ROOM); 2];
+        let pages = paginate(entries, 2);
+        assert_eq!(pages.len(), 2);
+        assert!(pages.iter().all(|page| page.len() > MESSAGE_LIMIT));
+        assert!(lengths(&pages)
+            .iter()
+            .all(|length| *length <= MESSAGE_LIMIT));
+    }
+
+    #[test]
+    fn cuts_an_entry_longer_than_the_limit() {
+        let pages = paginate(vec!["a".repeat(5000), "b".repeat(10)], 2);
+        assert_eq!(lengths(&pages), [MESSAGE_LIMIT - FOOTER_ROOM, 10]);
+        assert!(pages[0].chars().all(|c| c == 'a'));
+    }
+
+    #[test]
+    fn counts_servers_that_dont_fit() {
+        let entries = vec!["a".repeat(1000); MAX_PAGES + 3];
+        let pages = paginate(entries, MAX_PAGES + 13);
+        assert_eq!(pages.len(), MAX_PAGES);
+        assert!(pages.last().unwrap().ends_with("+13 more servers"));
+        assert!(lengths(&pages)
+            .iter()
+            .all(|length| *length <= MESSAGE_LIMIT));
+
+        // The limit leaving some out counts too, even when the rest fit
+        let pages = paginate(vec!["a".repeat(10)], 2);
+        assert_eq!(pages, [format!("{}+1 more server", "a".repeat(10))]);
+        assert_eq!(paginate(vec![], 0), [""]);
+    }
+
+    #[test]
+    fn fills_embed_messages_up_to_the_limits() {
+        // At most MAX_EMBEDS to a message...
+        let pages = paginate_embeds(vec![embed(100); embed::MAX_EMBEDS + 1], 11);
+        let counts: Vec<usize> = pages.iter().map(|page| page.embeds.len()).collect();
+        assert_eq!(counts, [embed::MAX_EMBEDS, 1]);
+
+        // ...and at most EMBED_LIMIT characters across them
+        let size = (embed::EMBED_LIMIT - FOOTER_ROOM) / 3;
+        let pages = paginate_embeds(vec![embed(size); 4], 4);
+        let counts: Vec<usize> = pages.iter().map(|page| page.embeds.len()).collect();
+        assert_eq!(counts, [3, 1]);
+    }
+
+    #[test]
+    fn clips_an_oversized_embed_to_fit_its_message() {
+        let fields = Embed {
+            title: "Server".to_string(),
+            fields: vec![("x".repeat(256), "x".repeat(1024)); embed::MAX_FIELDS],
+            ..Default::default()
+        };
+        let pages = paginate_embeds(vec![embed(2000), fields, embed(100)], 4);
+        let counts: Vec<usize> = pages.iter().map(|page| page.embeds.len()).collect();
+        assert_eq!(counts, [1, 2]);
+        assert_eq!(pages[1].embeds[0].fields.len(), 4);
+
+        // Still within the limit with the footer added to the last one
+        for page in pages {
+            let size: usize = page.embeds.iter().map(Embed::size).sum();
+            assert!(size <= embed::EMBED_LIMIT, "{} characters", size);
+        }
+    }
+
+    #[test]
+    fn counts_embeds_that_dont_fit() {
+        let pages = paginate_embeds(vec![embed(100); MAX_PAGES * embed::MAX_EMBEDS + 2], 60);
+        assert_eq!(pages.len(), MAX_PAGES);
+        let last = pages.last().unwrap().embeds.last().unwrap();
+        assert_eq!(last.footer.as_deref(), Some("+10 more servers"));
+        assert!(pages
+            .iter()
+            .flat_map(|page| &page.embeds[..page.embeds.len() - 1])
+            .all(|embed| embed.footer.is_none()));
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32) -> i64 {
+        chrono::Utc
+            .with_ymd_and_hms(year, month, day, hour, 0, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    #[test]
+    fn averages_recorded_hours_into_the_grid() {
+        // Mondays
+        let first = utc(2024, 1, 1, 0);
+        let second = utc(2024, 1, 8, 0);
+        let hours = HashMap::from([(first, 4.0), (first + 3600, 1.0)]);
+        let recorded = HashSet::from([first, first + 3600, second, second + 2 * 3600]);
+
+        let grid = weekday_hour_grid(&hours, &recorded, Tz::UTC);
+        // Nothing matching online the second Monday still counts...
+        assert_eq!(grid[0][0], Some(2.0));
+        assert_eq!(grid[0][2], Some(0.0));
+        // ...but the hour we weren't recording doesn't
+        assert_eq!(grid[0][1], Some(1.0));
+        assert_eq!(grid[0][3], None);
+        assert_eq!(grid[1], [None; 24]);
+    }
+
+    #[test]
+    fn places_hours_in_the_guilds_timezone() {
+        let monday = utc(2024, 1, 1, 0);
+        let hours = HashMap::from([(monday, 4.0)]);
+        let recorded = HashSet::from([monday]);
+
+        let grid = weekday_hour_grid(&hours, &recorded, chrono_tz::America::New_York);
+        assert_eq!(grid[6][19], Some(4.0));
+        assert_eq!(grid[0][0], None);
+    }
+
+    #[test]
+    fn follows_daylight_saving_changes() {
+        let london = chrono_tz::Europe::London;
+
+        // Clocks go forward from 01:00 to 02:00
+        let spring = utc(2024, 3, 31, 0);
+        let hours = HashMap::from([(spring, 2.0), (spring + 3600, 6.0)]);
+        let grid = weekday_hour_grid(&hours, &HashSet::new(), london);
+        assert_eq!(grid[6][0], Some(2.0));
+        assert_eq!(grid[6][1], None);
+        assert_eq!(grid[6][2], Some(6.0));
+
+        // Clocks go back from 02:00 to 01:00, so 01:00 happens twice
+        let autumn = utc(2024, 10, 27, 0);
+        let hours = HashMap::from([(autumn, 2.0)]);
+        let recorded = HashSet::from([autumn, autumn + 3600]);
+        let grid = weekday_hour_grid(&hours, &recorded, london);
+        assert_eq!(grid[6][0], None);
+        assert_eq!(grid[6][1], Some(1.0));
+        assert_eq!(grid[6][2], None);
+    }
+}
@@ -0,0 +1,432 @@
+use chrono::{TimeZone, Utc};
+use embedded_graphics::mono_font::ascii::FONT_7X13;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, RgbColor, Size};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+use embedded_graphics::{Drawable, Pixel};
+use tiny_skia::{Color, Paint, PathBuilder, Pixmap, PremultipliedColorU8, Rect, Stroke, Transform};
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 400;
+
+// Space around the plot for the title, legend and axis labels
+const LEFT: f32 = 50.0;
+const RIGHT: f32 = 20.0;
+const TOP: f32 = 50.0;
+const BOTTOM: f32 = 30.0;
+
+// Roughly Discord's dark theme, so charts sit nicely in a channel
+const BACKGROUND: (u8, u8, u8) = (0x2f, 0x31, 0x36);
+const GRID: (u8, u8, u8) = (0x4f, 0x54, 0x5c);
+const TEXT: (u8, u8, u8) = (0xdc, 0xdd, 0xde);
+
+const PALETTE: [(u8, u8, u8); 6] = [
+    (0x58, 0x65, 0xf2),
+    (0x57, 0xf2, 0x87),
+    (0xfe, 0xe7, 0x5c),
+    (0xeb, 0x45, 0x9e),
+    (0xed, 0x42, 0x45),
+    (0x3b, 0xa5, 0xdc),
+];
+
+/**
+ * One line on a chart: (unix time, value) points in time order
+ */
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(i64, f64)>,
+}
+
+fn color((r, g, b): (u8, u8, u8)) -> Color {
+    Color::from_rgba8(r, g, b, 255)
+}
+
+fn paint(rgb: (u8, u8, u8)) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color(color(rgb));
+    paint.anti_alias = true;
+    paint
+}
+
+/**
+ * A pixmap to draw charts on, with text drawn from a built-in bitmap font
+ */
+pub struct Canvas {
+    pixmap: Pixmap,
+}
+
+impl OriginDimensions for Canvas {
+    fn size(&self) -> Size {
+        Size::new(self.pixmap.width(), self.pixmap.height())
+    }
+}
+
+impl DrawTarget for Canvas {
+    type Color = Rgb888;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let width = self.pixmap.width() as i32;
+        let height = self.pixmap.height() as i32;
+        let data = self.pixmap.pixels_mut();
+        for Pixel(point, rgb) in pixels {
+            if point.x < 0 || point.y < 0 || point.x >= width || point.y >= height {
+                continue;
+            }
+            if let Some(pixel) = PremultipliedColorU8::from_rgba(rgb.r(), rgb.g(), rgb.b(), 255) {
+                data[(point.y * width + point.x) as usize] = pixel;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut pixmap = Pixmap::new(width, height).expect("Invalid chart size");
+        pixmap.fill(color(BACKGROUND));
+        Canvas { pixmap }
+    }
+
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, rgb: (u8, u8, u8)) {
+        if let Some(rect) = Rect::from_xywh(x, y, width, height) {
+            self.pixmap
+                .fill_rect(rect, &paint(rgb), Transform::identity(), None);
+        }
+    }
+
+    /**
+     * Draws a line through the points; None breaks the line
+     */
+    pub fn line(&mut self, points: &[Option<(f32, f32)>], width: f32, rgb: (u8, u8, u8)) {
+        let mut builder = PathBuilder::new();
+        let mut drawing = false;
+        for point in points {
+            match point {
+                Some((x, y)) if drawing => builder.line_to(*x, *y),
+                Some((x, y)) => {
+                    builder.move_to(*x, *y);
+                    drawing = true;
+                }
+                None => drawing = false,
+            }
+        }
+
+        if let Some(path) = builder.finish() {
+            let stroke = Stroke {
+                width,
+                ..Stroke::default()
+            };
+            self.pixmap
+                .stroke_path(&path, &paint(rgb), &stroke, Transform::identity(), None);
+        }
+    }
+
+    /**
+     * Writes text with its top at y, lined up on x according to alignment
+     */
+    pub fn text(&mut self, text: &str, x: f32, y: f32, alignment: Alignment, rgb: (u8, u8, u8)) {
+        let style = MonoTextStyle::new(&FONT_7X13, Rgb888::new(rgb.0, rgb.1, rgb.2));
+        let layout = TextStyleBuilder::new()
+            .alignment(alignment)
+            .baseline(Baseline::Top)
+            .build();
+        let _ =
+            Text::with_text_style(text, Point::new(x as i32, y as i32), style, layout).draw(self);
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        self.pixmap.encode_png().map_err(|err| err.to_string())
+    }
+}
+
+/**
+ * A round number to step the y axis by, giving at most five gridlines
+ */
+fn value_step(max: f64) -> f64 {
+    [1.0, 2.0, 5.0, 10.0, 20.0, 25.0, 50.0, 100.0, 200.0, 500.0]
+        .into_iter()
+        .find(|step| max / step <= 5.0)
+        .unwrap_or(1000.0)
+}
+
+/**
+ * How far apart to put time labels, and how to write them, for a time range
+ */
+fn time_step(range: i64) -> (i64, &'static str) {
+    const HOUR: i64 = 60 * 60;
+    const DAY: i64 = HOUR * 24;
+    match range {
+        r if r <= 2 * DAY => (3 * HOUR, "%H:%M"),
+        r if r <= 10 * DAY => (DAY, "%a %d"),
+        _ => (5 * DAY, "%d %b"),
+    }
+}
+
+/**
+ * Draws a line chart of each series between two times, as a PNG. Points
+ * further apart than `gap` seconds aren't joined up, so time a server spent
+ * offline shows as a break in its line
+ */
+pub fn line_chart(
+    title: &str,
+    series: &[Series],
+    from: i64,
+    to: i64,
+    gap: i64,
+) -> Result<Vec<u8>, String> {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    let plot_width = WIDTH as f32 - LEFT - RIGHT;
+    let plot_height = HEIGHT as f32 - TOP - BOTTOM;
+    let range = (to - from).max(1);
+
+    let max = series
+        .iter()
+        .flat_map(|series| series.points.iter().map(|(_, value)| *value))
+        .fold(4.0, f64::max);
+    let step = value_step(max);
+    let top = (max / step).ceil() * step;
+
+    let x = |time: i64| LEFT + (time - from) as f32 / range as f32 * plot_width;
+    let y = |value: f64| TOP + plot_height - (value / top) as f32 * plot_height;
+
+    canvas.text(title, LEFT, 8.0, Alignment::Left, TEXT);
+
+    // Horizontal gridlines with player counts
+    let mut value = 0.0;
+    while value <= top {
+        canvas.rect(LEFT, y(value), plot_width, 1.0, GRID);
+        canvas.text(
+            &format!("{}", value),
+            LEFT - 6.0,
+            y(value) - 6.0,
+            Alignment::Right,
+            TEXT,
+        );
+        value += step;
+    }
+
+    // Vertical gridlines with times
+    let (time_step, format) = time_step(range);
+    let mut time = from - from.rem_euclid(time_step) + time_step;
+    while time < to {
+        canvas.rect(x(time), TOP, 1.0, plot_height, GRID);
+        if let Some(label) = Utc.timestamp_opt(time, 0).single() {
+            let label = label.format(format).to_string();
+            canvas.text(
+                &label,
+                x(time),
+                TOP + plot_height + 6.0,
+                Alignment::Center,
+                TEXT,
+            );
+        }
+        time += time_step;
+    }
+
+    // Lines and the legend, with names shortened to fit on one line
+    let mut legend_x = LEFT;
+    let legend_chars = ((plot_width / series.len().max(1) as f32 / 7.0) as usize).saturating_sub(5);
+    for (index, series) in series.iter().enumerate() {
+        let rgb = PALETTE[index % PALETTE.len()];
+
+        let mut points = vec![];
+        let mut last = None;
+        for (time, value) in &series.points {
+            if last.is_some_and(|last| time - last > gap) {
+                points.push(None);
+            }
+            points.push(Some((x(*time), y(*value))));
+            last = Some(*time);
+        }
+        canvas.line(&points, 2.0, rgb);
+
+        let name: String = series.name.chars().take(legend_chars).collect();
+        canvas.rect(legend_x, 30.0, 10.0, 10.0, rgb);
+        canvas.text(&name, legend_x + 14.0, 28.0, Alignment::Left, TEXT);
+        legend_x += 14.0 + name.chars().count() as f32 * 7.0 + 16.0;
+    }
+
+    canvas.encode()
+}
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+// Heatmap cells, and the space left of and above them for labels
+const CELL: f32 = 30.0;
+const HEATMAP_LEFT: f32 = 40.0;
+const HEATMAP_TOP: f32 = 50.0;
+
+/**
+ * Picks a colour for a value between 0 and 1, from dark blue through pink to
+ * yellow
+ */
+fn heat(fraction: f64) -> (u8, u8, u8) {
+    const STOPS: [(u8, u8, u8); 3] = [(0x23, 0x2a, 0x5c), (0xeb, 0x45, 0x9e), (0xfe, 0xe7, 0x5c)];
+    let position = fraction.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let t = position - index as f64;
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+/**
+ * Draws a weekday by hour grid of values as a PNG, brighter where they're
+ * higher. Rows run Monday to Sunday and columns from midnight; hours with
+ * no data are left blank
+ */
+pub fn heatmap(title: &str, grid: &[[Option<f64>; 24]; 7]) -> Result<Vec<u8>, String> {
+    let width = HEATMAP_LEFT + 24.0 * CELL + 10.0;
+    let height = HEATMAP_TOP + 7.0 * CELL + 10.0;
+    let mut canvas = Canvas::new(width as u32, height as u32);
+
+    let max = grid
+        .iter()
+        .flatten()
+        .flatten()
+        .fold(1.0, |max: f64, value| max.max(*value));
+
+    canvas.text(title, HEATMAP_LEFT, 8.0, Alignment::Left, TEXT);
+    for hour in 0..24 {
+        let x = HEATMAP_LEFT + hour as f32 * CELL + CELL / 2.0;
+        canvas.text(&format!("{:02}", hour), x, 32.0, Alignment::Center, TEXT);
+    }
+
+    for (day, row) in grid.iter().enumerate() {
+        let y = HEATMAP_TOP + day as f32 * CELL;
+        canvas.text(WEEKDAYS[day], 6.0, y + 9.0, Alignment::Left, TEXT);
+
+        for (hour, value) in row.iter().enumerate() {
+            let x = HEATMAP_LEFT + hour as f32 * CELL;
+            let value = match value {
+                Some(value) => *value,
+                None => {
+                    canvas.rect(x + 1.0, y + 1.0, CELL - 2.0, CELL - 2.0, GRID);
+                    continue;
+                }
+            };
+
+            let fraction = value / max;
+            canvas.rect(x + 1.0, y + 1.0, CELL - 2.0, CELL - 2.0, heat(fraction));
+            if value >= 0.5 {
+                // Dark text on the bright cells
+                let rgb = if fraction > 0.6 { BACKGROUND } else { TEXT };
+                let label = format!("{}", value.round());
+                canvas.text(&label, x + CELL / 2.0, y + 9.0, Alignment::Center, rgb);
+            }
+        }
+    }
+
+    canvas.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 60 * 60;
+    const DAY: i64 = 24 * HOUR;
+    const NOW: i64 = 1_700_000_000;
+
+    fn size(png: &[u8]) -> (u32, u32) {
+        let pixmap = Pixmap::decode_png(png).unwrap();
+        (pixmap.width(), pixmap.height())
+    }
+
+    fn series(name: &str, points: usize) -> Series {
+        Series {
+            name: name.to_string(),
+            points: (0..points as i64)
+                .map(|index| (NOW - DAY + index * HOUR, (index % 7) as f64))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn draws_line_charts_of_any_number_of_series() {
+        let empty = line_chart("Players", &[], NOW - DAY, NOW, HOUR).unwrap();
+        assert_eq!(size(&empty), (WIDTH, HEIGHT));
+
+        let one = line_chart("Players", &[series("Alpha", 24)], NOW - DAY, NOW, HOUR).unwrap();
+        assert_eq!(size(&one), (WIDTH, HEIGHT));
+
+        // More series than colours, with names too long for the legend
+        let many: Vec<Series> = (0..20)
+            .map(|index| {
+                series(
+                    &format!("{} {}", "Growling Sidewinder".repeat(4), index),
+                    24,
+                )
+            })
+            .collect();
+        assert!(many.len() > PALETTE.len());
+        let many = line_chart("Players", &many, NOW - DAY, NOW, HOUR).unwrap();
+        assert_eq!(size(&many), (WIDTH, HEIGHT));
+    }
+
+    #[test]
+    fn draws_line_charts_over_odd_ranges() {
+        let busy = [Series {
+            name: "Busy".to_string(),
+            points: vec![(NOW - HOUR, 10_000.0), (NOW, 0.0)],
+        }];
+        for (from, to) in [(NOW, NOW), (NOW, NOW - DAY), (NOW - 30 * DAY, NOW)] {
+            let png = line_chart("Players", &busy, from, to, HOUR).unwrap();
+            assert_eq!(size(&png), (WIDTH, HEIGHT));
+        }
+    }
+
+    #[test]
+    fn steps_values_by_round_numbers() {
+        assert_eq!(value_step(4.0), 1.0);
+        assert_eq!(value_step(5.0), 1.0);
+        assert_eq!(value_step(5.1), 2.0);
+        assert_eq!(value_step(10.0), 2.0);
+        assert_eq!(value_step(10.1), 5.0);
+        assert_eq!(value_step(25.0), 5.0);
+        assert_eq!(value_step(25.1), 10.0);
+        assert_eq!(value_step(2500.0), 500.0);
+        assert_eq!(value_step(2500.1), 1000.0);
+    }
+
+    #[test]
+    fn steps_times_by_range() {
+        assert_eq!(time_step(DAY), (3 * HOUR, "%H:%M"));
+        assert_eq!(time_step(2 * DAY), (3 * HOUR, "%H:%M"));
+        assert_eq!(time_step(2 * DAY + 1), (DAY, "%a %d"));
+        assert_eq!(time_step(10 * DAY), (DAY, "%a %d"));
+        assert_eq!(time_step(10 * DAY + 1), (5 * DAY, "%d %b"));
+    }
+
+    #[test]
+    fn draws_heatmaps() {
+        let size_of_grid = (
+            (HEATMAP_LEFT + 24.0 * CELL + 10.0) as u32,
+            (HEATMAP_TOP + 7.0 * CELL + 10.0) as u32,
+        );
+        let empty = heatmap("Players", &[[None; 24]; 7]).unwrap();
+        assert_eq!(size(&empty), size_of_grid);
+
+        let mut grid = [[Some(0.0); 24]; 7];
+        grid[4][20] = Some(42.0);
+        grid[5][3] = None;
+        let busy = heatmap("Players", &grid).unwrap();
+        assert_eq!(size(&busy), size_of_grid);
+        assert_ne!(busy, empty);
+    }
+
+    #[test]
+    fn blends_heat_between_stops() {
+        assert_eq!(heat(0.0), (0x23, 0x2a, 0x5c));
+        assert_eq!(heat(0.5), (0xeb, 0x45, 0x9e));
+        assert_eq!(heat(1.0), (0xfe, 0xe7, 0x5c));
+        assert_eq!(heat(-1.0), heat(0.0));
+        assert_eq!(heat(2.0), heat(1.0));
+        assert_eq!(heat(0.25), (0x87, 0x38, 0x7d));
+    }
+}
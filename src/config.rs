@@ -0,0 +1,388 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufReader, Result};
+use std::str::FromStr;
+
+use crate::dcs::{Branch, DcsVersion, ServerId};
+use crate::embed::Page;
+use crate::filter::SubFilter;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Sub {
+    #[serde(default)]
+    pub name: String, // unique within its channel, for commands to refer to it by
+    pub message_id: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub page_ids: Vec<u64>, // messages after the first, for listings too long for one
+    pub filter: String,
+    #[serde(default)]
+    pub query: bool, // filter is in the query language; older ones are one phrase to find in names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>, // e.g. /^\[JTF\]/i, matched against names as well as the filter
+    #[serde(skip)]
+    pub matcher: SubFilter, // regex and filter, compiled
+    pub last_content: String,
+    #[serde(default)]
+    pub chart: bool, // attach a daily player chart to the message
+    #[serde(default)]
+    pub chart_attachment: Option<u64>,
+    #[serde(default)]
+    pub chart_updated: i64,
+    #[serde(default)]
+    pub fuzzy: bool, // match the filter's text loosely, allowing for typos
+    #[serde(default)]
+    pub sort: SortKey,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[serde(default = "default_limit")]
+    pub limit: usize, // most servers to list
+    #[serde(default)]
+    pub mode: SubMode,
+    #[serde(default)]
+    pub style: Style,
+    #[serde(default)]
+    pub offline: OfflineAction,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub server_messages: HashMap<ServerId, ServerMessage>, // for SubMode::Servers
+}
+
+/**
+ * How a subscription shows its servers
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubMode {
+    #[default]
+    Listing, // all of them in one listing (over several messages if need be)
+    Servers, // a message for each server, under a message with the count
+}
+
+/**
+ * How servers are drawn
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    #[default]
+    Markdown,
+    Embeds, // an embed per server
+    Fields, // an embed with a field per server
+}
+
+/**
+ * What happens to a server's message (in SubMode::Servers) when the server
+ * goes offline
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OfflineAction {
+    #[default]
+    Delete,
+    Mark, // edited to say it's offline, and deleted if it's still gone a day later
+}
+
+/**
+ * The message a server has to itself
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerMessage {
+    pub message_id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub offline_since: Option<i64>,
+    #[serde(skip)]
+    pub content: Page, // as last sent, so unchanged servers aren't edited
+}
+
+pub const DEFAULT_LIMIT: usize = 10;
+pub const MAX_LIMIT: usize = 50;
+// Most messages a subscription with a message per server will keep up to date
+pub const MAX_SERVER_MESSAGES: usize = 10;
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+/**
+ * What a subscription's listing is sorted by
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Players,
+    Name,
+    #[default]
+    Version,
+    Uptime,  // how long the mission has been running
+    Changed, // when we last saw anything about the server change
+}
+
+impl SortKey {
+    /**
+     * The order people usually want: A to Z for names, otherwise the most
+     * (players, newest version, etc.) first
+     */
+    pub fn default_order(&self) -> SortOrder {
+        match self {
+            SortKey::Name => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+        match text.to_lowercase().as_str() {
+            "players" => Ok(SortKey::Players),
+            "name" => Ok(SortKey::Name),
+            "version" => Ok(SortKey::Version),
+            "uptime" => Ok(SortKey::Uptime),
+            "changed" | "recent" => Ok(SortKey::Changed),
+            _ => Err(format!(
+                "Can't sort by '{}'; try players, name, version, uptime or changed",
+                text
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            SortKey::Players => "players",
+            SortKey::Name => "name",
+            SortKey::Version => "version",
+            SortKey::Uptime => "uptime",
+            SortKey::Changed => "changed",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+        match text.to_lowercase().as_str() {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(format!("'{}' isn't asc or desc", text)),
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortOrder::Asc => write!(f, "asc"),
+            SortOrder::Desc => write!(f, "desc"),
+        }
+    }
+}
+
+impl Sub {
+    /**
+     * The filter as it was typed, regex and all
+     */
+    pub fn describe(&self) -> String {
+        match &self.regex {
+            Some(regex) if self.filter.is_empty() => regex.clone(),
+            Some(regex) => format!("{} {}", regex, self.filter),
+            None => self.filter.clone(),
+        }
+    }
+}
+
+/**
+ * A name for a new subscription in a channel: the lowest number not already
+ * taken
+ */
+pub fn next_name(subs: &[Sub]) -> String {
+    (1..)
+        .map(|number: u32| number.to_string())
+        .find(|name| subs.iter().all(|sub| &sub.name != name))
+        .unwrap()
+}
+
+/**
+ * A channel that wants to hear about new DCS releases
+ */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReleaseSub {
+    pub role_id: Option<u64>,
+}
+
+/**
+ * Everything the bot keeps on disk
+ */
+#[derive(Serialize, Deserialize, Default)]
+pub struct Config {
+    pub channels: HashMap<u64, Vec<Sub>>, // channel_id : that channel's subscriptions
+    #[serde(default)]
+    pub releases: HashMap<u64, ReleaseSub>, // channel_id : release announcement settings
+    #[serde(default)]
+    pub announced: HashMap<Branch, DcsVersion>, // newest release we've announced per branch
+    #[serde(default)]
+    pub timezones: HashMap<u64, String>, // guild_id : timezone name, e.g. Europe/London
+}
+
+impl Config {
+    /**
+     * Load stored subscriptions from our file on disk
+     */
+    pub fn load(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        let reader = BufReader::new(file);
+
+        // Older versions of the bot saved only the channels map
+        let mut value: serde_json::Value = serde_json::from_reader(reader)?;
+        if value.get("channels").is_none() {
+            println!("Migrating config from the old format");
+            value = serde_json::json!({ "channels": value });
+        }
+
+        // ...and only one subscription per channel
+        if let Some(channels) = value["channels"].as_object_mut() {
+            for subs in channels.values_mut() {
+                if subs.is_object() {
+                    *subs = serde_json::Value::Array(vec![subs.take()]);
+                }
+            }
+        }
+        let mut config: Config = serde_json::from_value(value)?;
+
+        for subs in config.channels.values_mut() {
+            for index in 0..subs.len() {
+                if subs[index].name.is_empty() {
+                    subs[index].name = next_name(subs);
+                }
+            }
+        }
+
+        // Filters from before the query language keep matching on the name
+        // like they used to, even the ones that would parse as a query now
+        // (e.g. "red OR blue" was a server name, not two of them)
+        for sub in config.channels.values_mut().flatten() {
+            if sub.mode == SubMode::Servers {
+                sub.limit = sub.limit.min(MAX_SERVER_MESSAGES);
+            }
+            if !sub.query {
+                sub.matcher = SubFilter::phrase(&sub.describe());
+                continue;
+            }
+            sub.matcher = SubFilter::new(sub.regex.as_deref(), &sub.filter).unwrap_or_else(|err| {
+                println!("Filter '{}' matched as a name ({})", sub.describe(), err);
+                SubFilter::phrase(&sub.describe())
+            });
+        }
+        Ok(config)
+    }
+
+    /**
+     * Save subscriptions to our file on disk
+     * TODO: This might block the rest of the app; consider whether this
+     * should run in its own watch-channel-powered thread
+     */
+    pub fn save(&self, path: &str) -> Result<()> {
+        let file = OpenOptions::new()
+            .truncate(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dcs::Server;
+    use serde_json::json;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn server(name: &str) -> Server {
+        serde_json::from_value(json!({"NAME": name, "IP_ADDRESS": "192.0.2.1", "PORT": "10308"}))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_channels_only_config() {
+        let config = Config::load(&fixture("config_channels_only.json")).unwrap();
+        assert_eq!(config.channels.len(), 2);
+        assert!(config.releases.is_empty());
+
+        let subs = &config.channels[&111];
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].name, "1");
+        assert_eq!(subs[0].message_id, 1001);
+        assert_eq!(subs[0].filter, "australia");
+        assert!(!subs[0].query);
+        assert_eq!(subs[0].limit, DEFAULT_LIMIT);
+        assert_eq!(subs[0].mode, SubMode::Listing);
+
+        // Matched as the name it always was, not as a query
+        let sub = &config.channels[&222][0];
+        assert!(sub.matcher.matches(&server("Red or Blue PvP"), false));
+        assert!(!sub.matcher.matches(&server("Red Flag"), false));
+    }
+
+    #[test]
+    fn migrates_one_sub_per_channel_config() {
+        let config = Config::load(&fixture("config_one_sub.json")).unwrap();
+        let subs = &config.channels[&111];
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].name, "1");
+        assert_eq!(subs[0].message_id, 1001);
+        assert!(subs[0].chart);
+        assert_eq!(subs[0].chart_attachment, Some(3003));
+        assert_eq!(config.releases[&111].role_id, Some(4004));
+        assert_eq!(
+            config.announced[&Branch::OpenBeta].to_string(),
+            "2.9.3.51704"
+        );
+        assert_eq!(config.timezones[&999], "Europe/London");
+    }
+
+    #[test]
+    fn keeps_migrated_subs_when_saved() {
+        let mut config = Config::load(&fixture("config_channels_only.json")).unwrap();
+        let mut sub = config.channels[&111][0].clone();
+        sub.name = "syria".to_string();
+        sub.filter = "mission:syria OR red".to_string();
+        sub.query = true;
+        config.channels.get_mut(&111).unwrap().push(sub);
+
+        let path = std::env::temp_dir().join(format!("dcsbot-config-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        config.save(path).unwrap();
+        let reloaded = Config::load(path);
+        let _ = std::fs::remove_file(path);
+        let reloaded = reloaded.unwrap();
+
+        let subs = &reloaded.channels[&111];
+        let names: Vec<&str> = subs.iter().map(|sub| sub.name.as_str()).collect();
+        assert_eq!(names, ["1", "syria"]);
+        assert!(!subs[0].query);
+        assert!(subs[1].query);
+        assert!(subs[1].matcher.matches(&server("Red Flag"), false));
+        assert!(!reloaded.channels[&222][0]
+            .matcher
+            .matches(&server("Red Flag"), false));
+    }
+}
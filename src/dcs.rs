@@ -1,231 +1,116 @@
-use reqwest::header::HeaderMap;
-use serde::Deserialize;
+use async_trait::async_trait;
 use std::time::Duration;
 use tokio::sync::mpsc;
 
-/**
- * Structs for serde to be able to deserialize the json
- */
-#[derive(Deserialize, Clone, Debug)]
-#[allow(non_snake_case)]
-pub struct Server {
-    pub NAME: String,
-    pub MISSION_NAME: String,
-    pub PLAYERS: String,
-
-    pub IP_ADDRESS: String,
-    pub PORT: String,
-
-    pub DCS_VERSION: String,
-    //MISSION_TIME: String,
-    //PLAYERS_MAX: String,
-    //PASSWORD: String,
-    //DESCRIPTION: String,
-    //MISSION_TIME_FORMATTED: String,
-}
-
-#[derive(Deserialize, Clone, Debug)]
-#[allow(non_snake_case)]
-pub struct Servers {
-    pub SERVERS: Vec<Server>,
-    //SERVERS_MAX_COUNT: i32,
-    //SERVERS_MAX_DATE: String,
-    //PLAYERS_COUNT: i32,
-    //MY_SERVERS : Vec<Server>
-}
+use backoff::Backoff;
+use diff::Differ;
+
+mod backoff;
+mod changelog;
+mod diff;
+mod endpoints;
+mod error;
+mod file;
+mod identity;
+mod login;
+mod model;
+mod session;
+mod version;
+mod website;
+
+pub use backoff::RetryPolicy;
+pub use changelog::{Branch, Changelog, Release};
+pub use diff::ServerEvent;
+pub use endpoints::Endpoints;
+pub use error::DcsError;
+pub use file::FileSource;
+pub use identity::{IdentityTracker, ServerId};
+pub use model::{Server, Servers};
+pub use version::{DcsVersion, VersionReq};
+pub use website::WebsiteSource;
 
 #[derive(Debug)]
 pub enum ServersMessage {
     Servers(Servers),
-    Versions((String, String)),
+    Events(Vec<ServerEvent>),
+    Releases(Changelog),
 }
 
 /**
- * As get_all("set-cookie") doesn't work, we have to manually parse the separate
- * set-cookie lines into a single cookie string.
+ * Somewhere we can get server listings and version numbers from. The website
+ * scraper is the real thing; other implementations let the bot run against
+ * fixture data or stand-in servers without bot.rs knowing the difference
  */
-fn parse_cookie(headers: &HeaderMap) -> String {
-    let mut cookies = vec![];
-    for (key, value) in headers.iter() {
-        if key == "set-cookie" {
-            cookies.push(value.to_str().unwrap())
-        }
-    }
-    cookies.join(", ")
-}
-
+#[async_trait]
+pub trait ServerSource: Send {
+    /// Fetch the current snapshot of all servers
+    async fn fetch_servers(&mut self) -> Result<Servers, DcsError>;
 
-fn get_between(source: &str, start: &str, end: &str) -> Option<String> {
-    let start_pos = source.find(start)?;
-    let source = &source[start_pos + start.len()..];
-    let end_pos = source.find(end)?;
-    Some(source[..end_pos].to_string())
-}
-/**
- * Gets a login cookie from the DCS website
- */
-async fn login(username: String, password: String) -> Result<String, &'static str> {
-    println!("Logging in");
-
-    if username.is_empty() || password.is_empty() {
-        return Err("No username or password");
-    }
-
-    let hello = reqwest::Client::new()
-        .get("https://www.digitalcombatsimulator.com/en/personal/profile/?login=yes")
-        .timeout(Duration::from_secs(60))
-        .send()
-        .await;
-
-    let res = match hello {
-        Ok(res) => res,
-        Err(_e) => return Err("Failed to get login page"),
-    };
-
-    // Get PHP SessID
-    let hello_cookies = parse_cookie(res.headers());
-    let dcs_sessid = get_between(&hello_cookies, "DCSSESSID=", ";").unwrap();
-    println!("dcssessid: {}", dcs_sessid);
-
-    // Get session ID
-    let body = res.text().await.unwrap();
-    let sessid = get_between(&body, "bitrix_sessid\":\"", "\"});</script>").unwrap();
-    println!("Sessid: {}", sessid);
-
-    let mut login_headers = HeaderMap::new();
-    
-    login_headers.insert( "content-type",    "application/x-www-form-urlencoded".parse().unwrap() );
-    login_headers.insert( "Cookie", hello_cookies.parse().unwrap());
-
-    let login_result = reqwest::Client::new().post("https://www.digitalcombatsimulator.com/en/personal/profile/?login=yes")
-        .headers(login_headers)
-        .body(format!("sessid={}&AUTH_FORM=Y&TYPE=AUTH&backurl=%2Fen%2F&USER_LOGIN={}&USER_PASSWORD={}&USER_REMEMBER=1&Login", sessid, username, password))
-        .timeout(Duration::from_secs(60))
-        .send().await;
-
-    match login_result {
-        Ok(res) => {
-            let cookies = parse_cookie(res.headers());
-            //println!("Cookies: {}", cookies);
-            if !cookies.contains("DCS_UIDD=") {
-                return Err("username/password incorrect");
-            }
-        
-            let uidd = get_between(&cookies, "DCS_UIDD=", ";").unwrap();
-            let login = get_between(&cookies, "DCS_LOGIN=", ";").unwrap();
-            
-            // Create cookies string
-            let result = format!("DCS_UIDD={}; DCS_LOGIN={}; DCS_SOUND_LOGIN_PLAYED=Y; DCS_SALE_UID=0; DCSSESSID={}", uidd, login, dcs_sessid);
-            println!("Cookies: {}", result);
-        
-            return Ok(result)
-        },
-        Err(err) => {
-            println!("Login failed {:?}", err);
-            return Err("Login failed");
-        }
-    }
+    /// Fetch the releases listed on the changelog, newest first
+    async fn fetch_releases(&mut self) -> Result<Vec<Release>, DcsError>;
 }
 
 /**
- * Gets the current list of servers from the DCS website
+ * Polls the source until it fails, returning the error that stopped it
  */
-async fn get_servers(cookies: String) -> Result<Servers, String> {
-    println!("Getting servers");
-    let mut headers = HeaderMap::new();
-    headers.insert(reqwest::header::COOKIE, cookies.parse().unwrap());
-    headers.insert( "content-type", "application/x-www-form-urlencoded".parse().unwrap() );
-
-    let servers_result = reqwest::Client::new()
-        .get("https://www.digitalcombatsimulator.com/en/personal/server/?ajax=y")
-        .headers(headers)
-        .timeout(Duration::from_secs(120))
-        .send()
-        .await;
-
-    match servers_result {
-        Ok(servers) => match servers.json::<Servers>().await {
-            Ok(json) => Ok(json),
-            Err(err) => Err(format!("JSON parse error: {:?}", err)),
-        },
-        Err(err) => Err(format!("Load error: {:?}", err)),
-    }
-}
-
-async fn parse_versions(text: String) -> Result<(String, String), String> {
-    println!("Parsing versions");
-    let mut lines = text.split("/en/news/changelog/openbeta/");
-    let beta = match lines.nth(2) {
-        Some(line) => line.split("/").nth(0).unwrap(),
-        _ => return Err("Beta version not found".to_string()),
-    };
-
-    let mut lines = text.split("/en/news/changelog/release/");
-    let stable = match lines.nth(2) {
-        Some(line) => line.split("/").nth(0).unwrap(),
-        _ => return Err("Stable version not found".to_string()),
-    };
-
-    Ok((beta.to_string(), stable.to_string()))
-}
-
-async fn get_versions() -> Result<(String, String), String> {
-    let versions_result = reqwest::Client::new()
-        .get("https://www.digitalcombatsimulator.com/en/news/changelog/")
-        .timeout(Duration::from_secs(90))
-        .send()
-        .await;
-
-    match versions_result {
-        Ok(versions) => match versions.text().await {
-            Ok(text) => parse_versions(text).await,
-            Err(err) => Err(format!("Text parse error: {:?}", err)),
-        },
-        Err(err) => Err(format!("Load error: {:?}", err)),
-    }
-}
-
-async fn run_dcs(username: String, password: String, servers_tx: mpsc::Sender<ServersMessage>) {
-    let cookies = login(username, password).await;
-    if let Err(msg) = cookies {
-        return println!("\x1b[31mLogin failed: {}\x1b[0m", msg);
-    }
-
-    let cookie_string = cookies.unwrap();
+async fn run_dcs(
+    source: &mut dyn ServerSource,
+    servers_tx: &mpsc::Sender<ServersMessage>,
+    backoff: &mut Backoff,
+    changelog: &mut Changelog,
+    identities: &mut IdentityTracker,
+    differ: &mut Differ,
+) -> DcsError {
     let mut last_version_fetch = std::time::SystemTime::UNIX_EPOCH;
 
     loop {
         // Poll the DCS website every 3 hours to figure out what the latest
-        // Open Beta and Stable version numbers are
+        // Open Beta and Stable releases are
         let now = std::time::SystemTime::now();
         if now.duration_since(last_version_fetch).unwrap().as_secs() > 60 * 60 * 3 {
-            match get_versions().await {
-                Ok(versions) => {
-                    println!("Versions: {:?}", versions);
-                    let _ = servers_tx.send(ServersMessage::Versions(versions)).await;
+            match source.fetch_releases().await {
+                Ok(releases) => {
+                    for release in changelog.merge(releases) {
+                        println!(
+                            "Release: {} {} ({:?})",
+                            release.branch, release.version, release.date
+                        );
+                    }
+                    let _ = servers_tx
+                        .send(ServersMessage::Releases(changelog.clone()))
+                        .await;
                     last_version_fetch = now;
                 }
-                Err(err) => println!("dcs.rs run_dcs(): version fetch error: {:?}", err),
+                Err(err) => println!("dcs.rs run_dcs(): version fetch error: {}", err),
             }
         }
 
-        // Get the list of servers from the DCS website
-        match get_servers(cookie_string.to_string()).await {
-            Ok(servers) => {
+        // Get the list of servers from the source
+        match source.fetch_servers().await {
+            Ok(mut servers) => {
+                backoff.reset();
+                identities.resolve(&mut servers);
+                let events = differ.diff(&servers);
+
                 // As we are using regular channels instead of unbounded, this
                 // will block if channel is full (max 1 message). This can be
                 // caused if sending messages takes too long. We can consider
                 // threading the sending of messages in bot, but this seems like
                 // a reasonable rate limiter.
+                // Events go first, so the bot knows what changed before it
+                // renders the listing
+                if !events.is_empty() {
+                    let _ = servers_tx.send(ServersMessage::Events(events)).await;
+                }
                 let _ = servers_tx.send(ServersMessage::Servers(servers)).await;
             }
-            Err(msg) => {
-                println!("\x1b[31mFailed to get server list: {}\x1b[0m", msg);
+            Err(err) => {
+                println!("\x1b[31mFailed to get server list: {}\x1b[0m", err);
 
-                // Even though this might occur to due simple network errors,
-                // we fall out of the loop so we can do a complete do-over,
-                // in case the error is due to auth expiring
-                return;
+                // Sources retry what they can themselves (the website source
+                // re-logs in and retries network errors), so by now it's worth
+                // backing off for a while
+                return err;
             }
         }
 
@@ -233,12 +118,33 @@ async fn run_dcs(username: String, password: String, servers_tx: mpsc::Sender<Se
     }
 }
 
-pub async fn start(username: String, password: String, servers_tx: mpsc::Sender<ServersMessage>) {
+pub async fn start(
+    mut source: Box<dyn ServerSource>,
+    policy: RetryPolicy,
+    mut identities: IdentityTracker,
+    servers_tx: mpsc::Sender<ServersMessage>,
+) {
+    let mut backoff = Backoff::new(policy);
+    let mut changelog = Changelog::default();
+    let mut differ = Differ::default();
     loop {
-        run_dcs(username.clone(), password.clone(), servers_tx.clone()).await;
+        let err = run_dcs(
+            source.as_mut(),
+            &servers_tx,
+            &mut backoff,
+            &mut changelog,
+            &mut identities,
+            &mut differ,
+        )
+        .await;
 
-        // Only reaches this in case of failure - consider exponential backoff
-        println!("\x1b[31mdcs.rs error: Restarting in 120 seconds\x1b[0m");
-        tokio::time::sleep(Duration::from_secs(120)).await;
+        // Only reaches this in case of failure
+        let delay = backoff.delay_for(&err);
+        println!(
+            "\x1b[31mdcs.rs error: {}; restarting in {} seconds\x1b[0m",
+            err,
+            delay.as_secs()
+        );
+        tokio::time::sleep(delay).await;
     }
 }
@@ -0,0 +1,147 @@
+use rand::Rng;
+use std::time::Duration;
+
+use super::DcsError;
+
+/**
+ * How long dcs::start waits before trying again after a source fails
+ */
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Delay after the first failure; doubles with every failure after that
+    pub base: Duration,
+    /// Longest we'll wait between attempts
+    pub max: Duration,
+    /// Fraction of the delay to randomly add or remove (0.0 - 1.0)
+    pub jitter: f64,
+    /// Minimum wait after a rejected login, so a wrong password doesn't
+    /// hammer the login form
+    pub auth_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            base: Duration::from_secs(30),
+            max: Duration::from_secs(30 * 60),
+            jitter: 0.2,
+            auth_delay: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/**
+ * Exponential backoff state; reset whenever a poll succeeds
+ */
+pub struct Backoff {
+    policy: RetryPolicy,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Backoff {
+            policy,
+            failures: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    /**
+     * Records a failure and works out how long to wait before the next attempt.
+     * Only our own exponential delay is jittered; a Retry-After or the auth
+     * delay is a floor the jitter never takes us under
+     */
+    pub fn delay_for(&mut self, err: &DcsError) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+
+        let exponent = (self.failures - 1).min(16);
+        let exponential = self.jittered(
+            self.policy
+                .base
+                .saturating_mul(1 << exponent)
+                .min(self.policy.max),
+        );
+
+        match err {
+            DcsError::Auth(_) => exponential.max(self.policy.auth_delay),
+            // Honour Retry-After, even if it's longer than our own max
+            DcsError::RateLimited(Some(wait)) => exponential.max(*wait),
+            DcsError::RateLimited(None)
+            | DcsError::SessionExpired(_)
+            | DcsError::Parse(_)
+            | DcsError::Network(_)
+            | DcsError::Io(_) => exponential,
+        }
+    }
+
+    fn jittered(&self, delay: Duration) -> Duration {
+        let jitter = self.policy.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+        delay.mul_f64(factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            base: Duration::from_secs(30),
+            max: Duration::from_secs(30 * 60),
+            jitter,
+            auth_delay: Duration::from_secs(60 * 60),
+        }
+    }
+
+    #[test]
+    fn doubles_up_to_the_max() {
+        let mut backoff = Backoff::new(policy(0.0));
+        let err = DcsError::Network("down".to_string());
+        let delays: Vec<u64> = (0..8).map(|_| backoff.delay_for(&err).as_secs()).collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480, 960, 1800, 1800]);
+
+        backoff.reset();
+        assert_eq!(backoff.delay_for(&err), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn jitters_within_bounds() {
+        let mut backoff = Backoff::new(policy(0.2));
+        for _ in 0..100 {
+            backoff.reset();
+            let delay = backoff.delay_for(&DcsError::Network("down".to_string()));
+            assert!(delay >= Duration::from_secs(24) && delay <= Duration::from_secs(36));
+        }
+    }
+
+    #[test]
+    fn never_undercuts_retry_after() {
+        let wait = Duration::from_secs(600);
+        let mut backoff = Backoff::new(policy(1.0));
+        for _ in 0..100 {
+            backoff.reset();
+            assert!(backoff.delay_for(&DcsError::RateLimited(Some(wait))) >= wait);
+        }
+
+        // Even when it's longer than our own max
+        let wait = Duration::from_secs(2 * 60 * 60);
+        assert!(backoff.delay_for(&DcsError::RateLimited(Some(wait))) >= wait);
+    }
+
+    #[test]
+    fn never_undercuts_auth_delay() {
+        let mut backoff = Backoff::new(policy(1.0));
+        for _ in 0..100 {
+            let delay = backoff.delay_for(&DcsError::Auth("wrong password".to_string()));
+            assert!(delay >= Duration::from_secs(60 * 60));
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use chrono::NaiveDate;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use url::Url;
+
+use super::{DcsError, DcsVersion};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Branch {
+    OpenBeta,
+    Stable,
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Branch::OpenBeta => write!(f, "Open Beta"),
+            Branch::Stable => write!(f, "Stable"),
+        }
+    }
+}
+
+/**
+ * One entry on the DCS changelog page
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Release {
+    pub branch: Branch,
+    pub version: DcsVersion,
+    pub date: Option<NaiveDate>,
+    pub link: String,
+    pub headline: String,
+}
+
+impl Release {
+    fn same_as(&self, other: &Release) -> bool {
+        self.branch == other.branch && self.version == other.version
+    }
+}
+
+/**
+ * Every release we've seen, across however many fetches of the changelog
+ */
+#[derive(Default, Clone, Debug)]
+pub struct Changelog {
+    releases: Vec<Release>,
+}
+
+impl Changelog {
+    /**
+     * Adds any releases we haven't seen before, returning the new ones
+     */
+    pub fn merge(&mut self, releases: Vec<Release>) -> Vec<Release> {
+        let mut added = vec![];
+        for release in releases {
+            if !self.releases.iter().any(|known| known.same_as(&release)) {
+                self.releases.push(release.clone());
+                added.push(release);
+            }
+        }
+        added
+    }
+
+    /**
+     * The newest release on a branch, by version number
+     */
+    pub fn latest(&self, branch: Branch) -> Option<&Release> {
+        self.releases
+            .iter()
+            .filter(|release| release.branch == branch)
+            .max_by(|a, b| a.version.cmp(&b.version).then(a.date.cmp(&b.date)))
+    }
+}
+
+fn selector(selectors: &str) -> Selector {
+    Selector::parse(selectors).expect("Invalid selector")
+}
+
+/**
+ * Works out whether a link points at a release, e.g.
+ * /en/news/changelog/openbeta/2.9.3.51704/ or /en/news/changelog/release/2.9.2.49940/
+ */
+fn release_link(href: &str, base: &Url) -> Option<(Branch, DcsVersion, String)> {
+    let url = base.join(href).ok()?;
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let position = segments.iter().position(|s| *s == "changelog")?;
+
+    let branch = match *segments.get(position + 1)? {
+        "openbeta" => Branch::OpenBeta,
+        "release" | "stable" => Branch::Stable,
+        _ => return None,
+    };
+
+    let version = segments.get(position + 2)?;
+    if !version.contains('.') {
+        return None;
+    }
+    let version = version.parse::<DcsVersion>().ok()?;
+
+    Some((branch, version, url.to_string()))
+}
+
+/**
+ * Looks for a date in a run of text: 29.02.2024, 2024-02-29, 29/02/2024,
+ * February 29, 2024 or 29 February 2024
+ */
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| c == ',' || c == '(' || c == ')'))
+        .collect();
+
+    for (i, word) in words.iter().enumerate() {
+        for format in ["%d.%m.%Y", "%Y-%m-%d", "%d/%m/%Y"] {
+            if let Ok(date) = NaiveDate::parse_from_str(word, format) {
+                return Some(date);
+            }
+        }
+
+        if let Some(window) = words.get(i..i + 3) {
+            let joined = window.join(" ");
+            for format in ["%B %d %Y", "%d %B %Y", "%b %d %Y", "%d %b %Y"] {
+                if let Ok(date) = NaiveDate::parse_from_str(&joined, format) {
+                    return Some(date);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/**
+ * The element holding everything about one release: the biggest ancestor of
+ * its link that doesn't also contain links to other releases
+ */
+fn release_item<'a>(link: ElementRef<'a>, base: &Url) -> ElementRef<'a> {
+    let links = selector("a[href]");
+    let key = |element: ElementRef| {
+        let href = element.value().attr("href")?;
+        release_link(href, base).map(|(branch, version, _)| (branch, version))
+    };
+    let own = key(link);
+
+    let mut item = link;
+    for ancestor in link.ancestors().filter_map(ElementRef::wrap) {
+        let others = ancestor
+            .select(&links)
+            .filter_map(key)
+            .any(|other| Some(&other) != own.as_ref());
+        if others {
+            break;
+        }
+        item = ancestor;
+    }
+    item
+}
+
+/**
+ * Pulls every release listed on the changelog page, in page order. Rather
+ * than relying on the page layout, this finds links to individual releases
+ * and reads the date and headline from whatever element surrounds each one
+ */
+pub fn parse_changelog(page: &str, page_url: &str) -> Result<Vec<Release>, DcsError> {
+    println!("Parsing changelog");
+    let base = Url::parse(page_url)
+        .map_err(|err| DcsError::Parse(format!("Bad changelog URL {}: {}", page_url, err)))?;
+    let document = Html::parse_document(page);
+    let headings = selector("h1, h2, h3, h4, h5, h6");
+
+    let mut releases: Vec<Release> = vec![];
+    for link in document.select(&selector("a[href]")) {
+        let (branch, version, url) = match link
+            .value()
+            .attr("href")
+            .and_then(|href| release_link(href, &base))
+        {
+            Some(found) => found,
+            None => continue,
+        };
+        if releases
+            .iter()
+            .any(|r| r.branch == branch && r.version == version)
+        {
+            continue;
+        }
+
+        let item = release_item(link, &base);
+        let date = item.text().find_map(parse_date);
+        let headline = item
+            .select(&headings)
+            .map(|heading| collapse(&heading.text().collect::<String>()))
+            .find(|text| !text.is_empty())
+            .or_else(|| {
+                Some(collapse(&link.text().collect::<String>())).filter(|text| !text.is_empty())
+            })
+            .unwrap_or_else(|| format!("DCS {} {}", version, branch));
+
+        releases.push(Release {
+            branch,
+            version,
+            date,
+            link: url,
+            headline,
+        });
+    }
+
+    if releases.is_empty() {
+        return Err(DcsError::Parse(
+            "No releases found on changelog page".to_string(),
+        ));
+    }
+    Ok(releases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_URL: &str = "https://www.digitalcombatsimulator.com/en/news/changelog/";
+
+    fn fixture() -> Vec<Release> {
+        parse_changelog(
+            include_str!("../../tests/fixtures/changelog.html"),
+            PAGE_URL,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_every_release() {
+        let releases = fixture();
+        let listed: Vec<(Branch, String)> = releases
+            .iter()
+            .map(|release| (release.branch, release.version.to_string()))
+            .collect();
+
+        assert_eq!(
+            listed,
+            vec![
+                (Branch::OpenBeta, "2.9.3.51704".to_string()),
+                (Branch::Stable, "2.9.2.49940".to_string()),
+                (Branch::OpenBeta, "2.9.2.49940".to_string()),
+                (Branch::OpenBeta, "2.9.2.49629".to_string()),
+                (Branch::Stable, "2.9.1.48335".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_release_details() {
+        let release = &fixture()[1];
+
+        assert_eq!(release.branch, Branch::Stable);
+        assert_eq!(release.date, NaiveDate::from_ymd_opt(2024, 2, 15));
+        assert_eq!(
+            release.link,
+            "https://www.digitalcombatsimulator.com/en/news/changelog/release/2.9.2.49940/"
+        );
+        assert_eq!(release.headline, "DCS 2.9.2.49940 Stable");
+    }
+
+    #[test]
+    fn finds_newest_per_branch() {
+        let mut changelog = Changelog::default();
+        changelog.merge(fixture());
+
+        assert_eq!(
+            changelog
+                .latest(Branch::OpenBeta)
+                .unwrap()
+                .version
+                .to_string(),
+            "2.9.3.51704"
+        );
+        assert_eq!(
+            changelog
+                .latest(Branch::Stable)
+                .unwrap()
+                .version
+                .to_string(),
+            "2.9.2.49940"
+        );
+    }
+
+    #[test]
+    fn keeps_history_across_fetches() {
+        let mut changelog = Changelog::default();
+        assert_eq!(changelog.merge(fixture()).len(), 5);
+        assert!(changelog.merge(fixture()).is_empty());
+
+        let newer = Release {
+            branch: Branch::OpenBeta,
+            version: "2.9.3.51999".parse().unwrap(),
+            date: NaiveDate::from_ymd_opt(2024, 3, 7),
+            link: "https://www.digitalcombatsimulator.com/en/news/changelog/openbeta/2.9.3.51999/"
+                .to_string(),
+            headline: "DCS 2.9.3.51999 Open Beta".to_string(),
+        };
+        assert_eq!(changelog.merge(vec![newer]).len(), 1);
+        assert_eq!(
+            changelog
+                .latest(Branch::OpenBeta)
+                .unwrap()
+                .version
+                .to_string(),
+            "2.9.3.51999"
+        );
+    }
+
+    #[test]
+    fn newest_is_by_version_number() {
+        let release = |version: &str| Release {
+            branch: Branch::OpenBeta,
+            version: version.parse().unwrap(),
+            date: None,
+            link: String::new(),
+            headline: String::new(),
+        };
+
+        let mut changelog = Changelog::default();
+        changelog.merge(vec![release("2.9.9.1000"), release("2.9.10.999")]);
+        assert_eq!(
+            changelog
+                .latest(Branch::OpenBeta)
+                .unwrap()
+                .version
+                .to_string(),
+            "2.9.10.999"
+        );
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(
+            parse_date("Posted 2024-02-29"),
+            NaiveDate::from_ymd_opt(2024, 2, 29)
+        );
+        assert_eq!(
+            parse_date("February 29, 2024"),
+            NaiveDate::from_ymd_opt(2024, 2, 29)
+        );
+        assert_eq!(
+            parse_date("29 Feb 2024"),
+            NaiveDate::from_ymd_opt(2024, 2, 29)
+        );
+        assert_eq!(parse_date("DCS 2.9.3.51704 Open Beta"), None);
+    }
+
+    #[test]
+    fn rejects_pages_without_releases() {
+        let page = include_str!("../../tests/fixtures/maintenance_page.html");
+        assert!(parse_changelog(page, PAGE_URL).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use super::{DcsVersion, Server, ServerId, Servers};
+
+// Mission clocks can wobble a little between polls without a restart
+const RESTART_SLOP: Duration = Duration::from_secs(60);
+
+/**
+ * Something that changed between two polls
+ */
+#[derive(Clone, Debug)]
+pub enum ServerEvent {
+    Online(Server),
+    /// Carries the server as it was last seen
+    Offline(Server),
+    Renamed {
+        id: ServerId,
+        old: String,
+        new: String,
+    },
+    MissionChanged {
+        id: ServerId,
+        old: String,
+        new: String,
+    },
+    /// Same mission, but its clock went backwards
+    MissionRestarted {
+        id: ServerId,
+        mission: String,
+    },
+    PlayersChanged {
+        id: ServerId,
+        old: u32,
+        new: u32,
+    },
+    VersionChanged {
+        id: ServerId,
+        old: DcsVersion,
+        new: DcsVersion,
+    },
+}
+
+impl ServerEvent {
+    pub fn id(&self) -> &ServerId {
+        match self {
+            ServerEvent::Online(server) | ServerEvent::Offline(server) => &server.id,
+            ServerEvent::Renamed { id, .. }
+            | ServerEvent::MissionChanged { id, .. }
+            | ServerEvent::MissionRestarted { id, .. }
+            | ServerEvent::PlayersChanged { id, .. }
+            | ServerEvent::VersionChanged { id, .. } => id,
+        }
+    }
+}
+
+impl fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerEvent::Online(server) => write!(f, "{} online: {}", self.id(), server.NAME),
+            ServerEvent::Offline(server) => write!(f, "{} offline: {}", self.id(), server.NAME),
+            ServerEvent::Renamed { id, old, new } => {
+                write!(f, "{} renamed: {} -> {}", id, old, new)
+            }
+            ServerEvent::MissionChanged { id, old, new } => {
+                write!(f, "{} mission changed: {} -> {}", id, old, new)
+            }
+            ServerEvent::MissionRestarted { id, mission } => {
+                write!(f, "{} mission restarted: {}", id, mission)
+            }
+            ServerEvent::PlayersChanged { id, old, new } => {
+                write!(f, "{} players: {} -> {}", id, old, new)
+            }
+            ServerEvent::VersionChanged { id, old, new } => {
+                write!(f, "{} version: {} -> {}", id, old, new)
+            }
+        }
+    }
+}
+
+/**
+ * Compares each snapshot with the one before it. The very first snapshot
+ * reports every server as coming online, so consumers that track uptime get
+ * a starting point
+ */
+#[derive(Default)]
+pub struct Differ {
+    previous: HashMap<ServerId, Server>,
+}
+
+impl Differ {
+    pub fn diff(&mut self, servers: &Servers) -> Vec<ServerEvent> {
+        let mut events = vec![];
+        let mut current = HashMap::with_capacity(servers.SERVERS.len());
+
+        for server in &servers.SERVERS {
+            if current.contains_key(&server.id) {
+                // Listed twice; the first one wins
+                continue;
+            }
+
+            match self.previous.get(&server.id) {
+                None => events.push(ServerEvent::Online(server.clone())),
+                Some(old) => changes(&server.id, old, server, &mut events),
+            }
+            current.insert(server.id.clone(), server.clone());
+        }
+
+        for (id, old) in self.previous.drain() {
+            if !current.contains_key(&id) {
+                events.push(ServerEvent::Offline(old));
+            }
+        }
+
+        self.previous = current;
+        events
+    }
+}
+
+fn changes(id: &ServerId, old: &Server, new: &Server, events: &mut Vec<ServerEvent>) {
+    if old.NAME != new.NAME {
+        events.push(ServerEvent::Renamed {
+            id: id.clone(),
+            old: old.NAME.clone(),
+            new: new.NAME.clone(),
+        });
+    }
+
+    if old.MISSION_NAME != new.MISSION_NAME {
+        events.push(ServerEvent::MissionChanged {
+            id: id.clone(),
+            old: old.MISSION_NAME.clone(),
+            new: new.MISSION_NAME.clone(),
+        });
+    } else if new.MISSION_TIME + RESTART_SLOP < old.MISSION_TIME {
+        events.push(ServerEvent::MissionRestarted {
+            id: id.clone(),
+            mission: new.MISSION_NAME.clone(),
+        });
+    }
+
+    if old.PLAYERS != new.PLAYERS {
+        events.push(ServerEvent::PlayersChanged {
+            id: id.clone(),
+            old: old.PLAYERS,
+            new: new.PLAYERS,
+        });
+    }
+
+    if old.DCS_VERSION != new.DCS_VERSION {
+        events.push(ServerEvent::VersionChanged {
+            id: id.clone(),
+            old: old.DCS_VERSION.clone(),
+            new: new.DCS_VERSION.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn server(id: &str, fields: Value) -> Value {
+        let mut entry = json!({
+            "NAME": format!("Server {}", id),
+            "IP_ADDRESS": "192.0.2.1",
+            "PORT": "10308",
+            "MISSION_NAME": "Caucasus Training",
+            "MISSION_TIME": "3600",
+            "PLAYERS": "4",
+            "DCS_VERSION": "2.9.3.51704",
+            "id": id,
+        });
+        for (key, value) in fields.as_object().unwrap() {
+            entry[key] = value.clone();
+        }
+        entry
+    }
+
+    /**
+     * A snapshot of the given entries, with each one's id taken from its "id"
+     * (which the listing itself doesn't have; IdentityTracker fills it in)
+     */
+    fn snapshot(entries: Vec<Value>) -> Servers {
+        let ids: Vec<String> = entries
+            .iter()
+            .map(|entry| entry["id"].as_str().unwrap().to_string())
+            .collect();
+        let mut servers: Servers = serde_json::from_value(json!({ "SERVERS": entries })).unwrap();
+        for (server, id) in servers.SERVERS.iter_mut().zip(ids) {
+            server.id = ServerId::from(id);
+        }
+        servers
+    }
+
+    fn described(events: &[ServerEvent]) -> Vec<String> {
+        let mut described: Vec<String> = events.iter().map(ToString::to_string).collect();
+        described.sort();
+        described
+    }
+
+    #[test]
+    fn reports_servers_coming_online_and_going_offline() {
+        let mut differ = Differ::default();
+        let first = differ.diff(&snapshot(vec![
+            server("a", json!({})),
+            server("b", json!({})),
+        ]));
+        assert_eq!(
+            described(&first),
+            ["a online: Server a", "b online: Server b"]
+        );
+
+        let second = differ.diff(&snapshot(vec![
+            server("b", json!({})),
+            server("c", json!({})),
+        ]));
+        assert_eq!(
+            described(&second),
+            ["a offline: Server a", "c online: Server c"]
+        );
+
+        assert!(differ
+            .diff(&snapshot(vec![
+                server("b", json!({})),
+                server("c", json!({}))
+            ]))
+            .is_empty());
+    }
+
+    #[test]
+    fn reports_renames() {
+        let mut differ = Differ::default();
+        differ.diff(&snapshot(vec![server("a", json!({"NAME": "Old Name"}))]));
+        let events = differ.diff(&snapshot(vec![server("a", json!({"NAME": "New Name"}))]));
+        assert_eq!(described(&events), ["a renamed: Old Name -> New Name"]);
+    }
+
+    #[test]
+    fn reports_mission_changes_over_restarts() {
+        let mut differ = Differ::default();
+        differ.diff(&snapshot(vec![server("a", json!({}))]));
+
+        // A new mission starting its clock again is a change, not a restart
+        let events = differ.diff(&snapshot(vec![server(
+            "a",
+            json!({"MISSION_NAME": "Syria PvP", "MISSION_TIME": "10"}),
+        )]));
+        assert_eq!(
+            described(&events),
+            ["a mission changed: Caucasus Training -> Syria PvP"]
+        );
+    }
+
+    #[test]
+    fn reports_restarts() {
+        let mut differ = Differ::default();
+        differ.diff(&snapshot(vec![server("a", json!({}))]));
+
+        // The clock wobbling back a little isn't a restart
+        let events = differ.diff(&snapshot(vec![server(
+            "a",
+            json!({"MISSION_TIME": "3570"}),
+        )]));
+        assert!(events.is_empty(), "{:?}", described(&events));
+
+        let events = differ.diff(&snapshot(vec![server("a", json!({"MISSION_TIME": "30"}))]));
+        assert_eq!(
+            described(&events),
+            ["a mission restarted: Caucasus Training"]
+        );
+    }
+
+    #[test]
+    fn reports_player_and_version_changes() {
+        let mut differ = Differ::default();
+        differ.diff(&snapshot(vec![server("a", json!({}))]));
+        let events = differ.diff(&snapshot(vec![server(
+            "a",
+            json!({"PLAYERS": "9", "DCS_VERSION": "2.9.4.53549"}),
+        )]));
+        assert_eq!(
+            described(&events),
+            ["a players: 4 -> 9", "a version: 2.9.3.51704 -> 2.9.4.53549"]
+        );
+    }
+
+    #[test]
+    fn takes_the_first_of_duplicate_entries() {
+        let mut differ = Differ::default();
+        let events = differ.diff(&snapshot(vec![
+            server("a", json!({"PLAYERS": "4"})),
+            server("a", json!({"PLAYERS": "20"})),
+        ]));
+        assert_eq!(described(&events), ["a online: Server a"]);
+
+        // The duplicate doesn't look like a change either
+        let events = differ.diff(&snapshot(vec![
+            server("a", json!({"PLAYERS": "4"})),
+            server("a", json!({"PLAYERS": "20"})),
+        ]));
+        assert!(events.is_empty(), "{:?}", described(&events));
+    }
+}
@@ -0,0 +1,57 @@
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+
+/**
+ * Where the website source finds things. Defaults to the real DCS website;
+ * can be loaded from a JSON file (any subset of the fields) and overridden
+ * from the command line, e.g. to point at a local stand-in, a caching proxy or
+ * a mirror
+ */
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Endpoints {
+    pub base_url: String,
+    pub login_path: String,
+    pub servers_path: String,
+    pub changelog_path: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            base_url: "https://www.digitalcombatsimulator.com".to_string(),
+            login_path: "/en/personal/profile/?login=yes".to_string(),
+            servers_path: "/en/personal/server/?ajax=y".to_string(),
+            changelog_path: "/en/news/changelog/".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("Could not open {}: {:?}", path, err))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| format!("Could not parse {}: {:?}", path, err))
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    pub fn login_url(&self) -> String {
+        self.url(&self.login_path)
+    }
+
+    pub fn servers_url(&self) -> String {
+        self.url(&self.servers_path)
+    }
+
+    pub fn changelog_url(&self) -> String {
+        self.url(&self.changelog_path)
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+use std::time::Duration;
+
+/**
+ * Everything that can go wrong getting data out of a ServerSource, split up by
+ * what we should do about it (see Backoff::delay_for)
+ */
+#[derive(Debug)]
+pub enum DcsError {
+    /// The website turned down our login; retrying soon won't help
+    Auth(String),
+    /// We were logged in, but the website no longer thinks so
+    SessionExpired(String),
+    /// HTTP 429/503, with how long the server asked us to wait (if it said)
+    RateLimited(Option<Duration>),
+    /// Got a response but couldn't make sense of it (e.g. the layout changed)
+    Parse(String),
+    /// Couldn't reach the server, or it fell over
+    Network(String),
+    /// Local files for the file source
+    Io(String),
+}
+
+impl fmt::Display for DcsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DcsError::Auth(msg) => write!(f, "Authentication failed: {}", msg),
+            DcsError::SessionExpired(msg) => write!(f, "Session expired: {}", msg),
+            DcsError::RateLimited(Some(wait)) => {
+                write!(f, "Rate limited (retry after {}s)", wait.as_secs())
+            }
+            DcsError::RateLimited(None) => write!(f, "Rate limited"),
+            DcsError::Parse(msg) => write!(f, "Parse error: {}", msg),
+            DcsError::Network(msg) => write!(f, "Network error: {}", msg),
+            DcsError::Io(msg) => write!(f, "File error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DcsError {}
+
+impl From<reqwest::Error> for DcsError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            DcsError::Parse(format!("{:?}", err))
+        } else {
+            DcsError::Network(format!("{:?}", err))
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use async_trait::async_trait;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use super::changelog::parse_changelog;
+use super::{DcsError, Endpoints, Release, ServerSource, Servers};
+
+const CHANGELOG_FILE: &str = "changelog.html";
+
+/**
+ * Reads server listings from JSON files on disk, in the same format the DCS
+ * website serves them. The path can either be a single file, which is re-read
+ * on every poll, or a directory, in which case each poll moves on to the next
+ * *.json file (sorted by name, wrapping around at the end) so a recorded
+ * sequence of snapshots can be played back.
+ *
+ * Releases are read from a changelog.html file next to the listing(s): a saved
+ * copy of the DCS changelog page, or anything laid out like it
+ */
+pub struct FileSource {
+    path: PathBuf,
+    next_index: usize,
+}
+
+impl FileSource {
+    pub fn new(path: String) -> Self {
+        FileSource {
+            path: PathBuf::from(path),
+            next_index: 0,
+        }
+    }
+
+    fn changelog_path(&self) -> PathBuf {
+        if self.path.is_dir() {
+            self.path.join(CHANGELOG_FILE)
+        } else {
+            self.path.with_file_name(CHANGELOG_FILE)
+        }
+    }
+
+    /**
+     * Works out which file to read for this poll
+     */
+    fn next_snapshot(&mut self) -> Result<PathBuf, DcsError> {
+        if !self.path.is_dir() {
+            return Ok(self.path.clone());
+        }
+
+        let entries = std::fs::read_dir(&self.path)
+            .map_err(|err| DcsError::Io(format!("Could not read {:?}: {:?}", self.path, err)))?;
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        files.sort();
+
+        if files.is_empty() {
+            return Err(DcsError::Io(format!(
+                "No server snapshots found in {:?}",
+                self.path
+            )));
+        }
+
+        let file = files[self.next_index % files.len()].clone();
+        self.next_index = (self.next_index + 1) % files.len();
+        Ok(file)
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, DcsError> {
+    let file = File::open(path)
+        .map_err(|err| DcsError::Io(format!("Could not open {:?}: {:?}", path, err)))?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| DcsError::Parse(format!("JSON parse error in {:?}: {:?}", path, err)))
+}
+
+#[async_trait]
+impl ServerSource for FileSource {
+    async fn fetch_servers(&mut self) -> Result<Servers, DcsError> {
+        let path = self.next_snapshot()?;
+        println!("Reading servers from {:?}", path);
+        read_json(&path)
+    }
+
+    async fn fetch_releases(&mut self) -> Result<Vec<Release>, DcsError> {
+        let path = self.changelog_path();
+        let page = std::fs::read_to_string(&path)
+            .map_err(|err| DcsError::Io(format!("Could not read {:?}: {:?}", path, err)))?;
+
+        // Links in a saved page are relative to the real changelog
+        parse_changelog(&page, &Endpoints::default().changelog_url())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * An empty directory of its own for each test
+     */
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dcsbot-file-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_snapshot(dir: &Path, file: &str, name: &str) {
+        let listing = format!(
+            r#"{{"SERVERS": [{{"NAME": "{}", "IP_ADDRESS": "192.0.2.1", "PORT": "10308"}}]}}"#,
+            name
+        );
+        std::fs::write(dir.join(file), listing).unwrap();
+    }
+
+    async fn next_name(source: &mut FileSource) -> String {
+        source.fetch_servers().await.unwrap().SERVERS[0]
+            .NAME
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn rereads_a_single_file() {
+        let dir = temp_dir("single");
+        write_snapshot(&dir, "servers.json", "Alpha");
+        let mut source = FileSource::new(dir.join("servers.json").to_str().unwrap().to_string());
+        assert_eq!(next_name(&mut source).await, "Alpha");
+        assert_eq!(next_name(&mut source).await, "Alpha");
+
+        write_snapshot(&dir, "servers.json", "Bravo");
+        assert_eq!(next_name(&mut source).await, "Bravo");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn plays_back_a_directory_in_order() {
+        let dir = temp_dir("directory");
+        write_snapshot(&dir, "02.json", "Bravo");
+        write_snapshot(&dir, "01.json", "Alpha");
+        write_snapshot(&dir, "03.json", "Charlie");
+        std::fs::write(dir.join("notes.txt"), "not a snapshot").unwrap();
+
+        let mut source = FileSource::new(dir.to_str().unwrap().to_string());
+        let mut names = vec![];
+        for _ in 0..4 {
+            names.push(next_name(&mut source).await);
+        }
+        assert_eq!(names, ["Alpha", "Bravo", "Charlie", "Alpha"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn reports_missing_and_malformed_snapshots() {
+        let dir = temp_dir("empty");
+        std::fs::write(dir.join("notes.txt"), "not a snapshot").unwrap();
+        let mut source = FileSource::new(dir.to_str().unwrap().to_string());
+        assert!(matches!(source.fetch_servers().await, Err(DcsError::Io(_))));
+
+        std::fs::write(dir.join("broken.json"), "{\"SERVERS\": [").unwrap();
+        assert!(matches!(
+            source.fetch_servers().await,
+            Err(DcsError::Parse(_))
+        ));
+
+        let mut missing = FileSource::new(dir.join("gone.json").to_str().unwrap().to_string());
+        assert!(matches!(
+            missing.fetch_servers().await,
+            Err(DcsError::Io(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn reads_the_changelog_next_to_the_snapshots() {
+        let dir = temp_dir("changelog");
+        write_snapshot(&dir, "servers.json", "Alpha");
+        std::fs::write(
+            dir.join(CHANGELOG_FILE),
+            include_str!("../../tests/fixtures/changelog.html"),
+        )
+        .unwrap();
+
+        let mut from_dir = FileSource::new(dir.to_str().unwrap().to_string());
+        let mut from_file = FileSource::new(dir.join("servers.json").to_str().unwrap().to_string());
+        let releases = from_dir.fetch_releases().await.unwrap();
+        assert_eq!(releases.len(), 5);
+        assert_eq!(from_file.fetch_releases().await.unwrap().len(), 5);
+
+        std::fs::remove_file(dir.join(CHANGELOG_FILE)).unwrap();
+        assert!(matches!(
+            from_dir.fetch_releases().await,
+            Err(DcsError::Io(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,375 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use super::Servers;
+
+// How many names to remember per server
+const NAME_HISTORY: usize = 10;
+
+// Forget servers we haven't seen for this long
+const EXPIRY: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+/**
+ * A stable identity for a server, so we can tell it's the same one after it
+ * renames itself or moves host. Made from the IP:port it was first seen at
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ServerId(String);
+
+impl From<String> for ServerId {
+    fn from(id: String) -> Self {
+        ServerId(id)
+    }
+}
+
+impl fmt::Display for ServerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Identity {
+    addr: SocketAddr,
+    names: Vec<String>, // oldest first; the last one is the current name
+    last_seen: SystemTime,
+}
+
+/**
+ * Keeps track of which servers are which across polls. Servers are matched on
+ * address and name first. A server that doesn't match that way but has the
+ * same name as exactly one known server we haven't seen is taken to be that
+ * server, moved to a new host; failing that, one at a known address is that
+ * server, renamed
+ */
+#[derive(Serialize, Deserialize, Default)]
+pub struct IdentityTracker {
+    identities: HashMap<ServerId, Identity>,
+    #[serde(skip)]
+    by_addr: HashMap<SocketAddr, ServerId>,
+    #[serde(skip)]
+    path: String,
+}
+
+impl IdentityTracker {
+    /**
+     * Loads what we knew last time we ran, if anything. An empty path keeps
+     * everything in memory
+     */
+    pub fn load(path: String) -> Self {
+        let mut tracker = match OpenOptions::new().read(true).open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+                println!("\x1b[31mError reading identities: {:?}\x1b[0m", err);
+                IdentityTracker::default()
+            }),
+            Err(_) => IdentityTracker::default(),
+        };
+
+        tracker.by_addr = tracker
+            .identities
+            .iter()
+            .map(|(id, identity)| (identity.addr, id.clone()))
+            .collect();
+        tracker.path = path;
+        tracker
+    }
+
+    fn save(&self) {
+        if self.path.is_empty() {
+            return;
+        }
+        let result = OpenOptions::new()
+            .truncate(true)
+            .write(true)
+            .create(true)
+            .open(&self.path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| serde_json::to_writer(file, self).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            println!("\x1b[31mError saving identities: {}\x1b[0m", err);
+        }
+    }
+
+    /**
+     * Works out the identity of every server in the snapshot, filling in their
+     * `id` and `former_names`
+     */
+    pub fn resolve(&mut self, servers: &mut Servers) {
+        let now = SystemTime::now();
+        let mut changed = false;
+        let mut seen = HashSet::new();
+        let mut unknown = vec![];
+
+        // Servers still where we last saw them, under the same name
+        for (index, server) in servers.SERVERS.iter_mut().enumerate() {
+            let addr = SocketAddr::new(server.IP_ADDRESS, server.PORT);
+            let known = self.by_addr.get(&addr).filter(|id| {
+                !seen.contains(*id)
+                    && self
+                        .identities
+                        .get(*id)
+                        .is_some_and(|identity| identity.names.last() == Some(&server.NAME))
+            });
+            match known {
+                Some(id) => {
+                    server.id = id.clone();
+                    seen.insert(id.clone());
+                }
+                None => unknown.push(index),
+            }
+        }
+
+        // Known servers that moved host
+        let mut renamed = vec![];
+        for index in unknown {
+            let server = &mut servers.SERVERS[index];
+            let addr = SocketAddr::new(server.IP_ADDRESS, server.PORT);
+
+            let mut moved = self.identities.iter().filter(|(id, identity)| {
+                !seen.contains(*id) && identity.names.last() == Some(&server.NAME)
+            });
+            // Only if exactly one server fits the bill
+            let id = match (moved.next(), moved.next()) {
+                (Some((id, identity)), None) => {
+                    println!(
+                        "Server {} moved from {} to {}",
+                        server.NAME, identity.addr, addr
+                    );
+                    id.clone()
+                }
+                _ => {
+                    renamed.push(index);
+                    continue;
+                }
+            };
+
+            self.relocate(&id, addr, now);
+            server.id = id.clone();
+            seen.insert(id);
+            changed = true;
+        }
+
+        // Everything else is either a known server that renamed itself, or new
+        for index in renamed {
+            let server = &mut servers.SERVERS[index];
+            let addr = SocketAddr::new(server.IP_ADDRESS, server.PORT);
+
+            let id = match self.by_addr.get(&addr) {
+                Some(id) if !seen.contains(id) => id.clone(),
+                _ => {
+                    let id = self.new_id(addr);
+                    self.relocate(&id, addr, now);
+                    changed = true;
+                    id
+                }
+            };
+
+            server.id = id.clone();
+            seen.insert(id);
+        }
+
+        // Record names and when we saw everyone
+        for server in servers.SERVERS.iter_mut() {
+            let identity = match self.identities.get_mut(&server.id) {
+                Some(identity) => identity,
+                None => continue,
+            };
+            identity.last_seen = now;
+
+            if identity.names.last() != Some(&server.NAME) {
+                identity.names.retain(|name| *name != server.NAME);
+                identity.names.push(server.NAME.clone());
+                if identity.names.len() > NAME_HISTORY {
+                    identity.names.remove(0);
+                }
+                changed = true;
+            }
+
+            server.former_names = identity.names[..identity.names.len() - 1].to_vec();
+        }
+
+        changed |= self.expire(now);
+        if changed {
+            self.save();
+        }
+    }
+
+    /**
+     * Points an identity (a new one, if it isn't known) at the address its
+     * server is at now
+     */
+    fn relocate(&mut self, id: &ServerId, addr: SocketAddr, now: SystemTime) {
+        let identity = self.identities.entry(id.clone()).or_insert(Identity {
+            addr,
+            names: vec![],
+            last_seen: now,
+        });
+        // Another server may have taken over the old address earlier on
+        if self.by_addr.get(&identity.addr) == Some(id) {
+            self.by_addr.remove(&identity.addr);
+        }
+        identity.addr = addr;
+        self.by_addr.insert(addr, id.clone());
+    }
+
+    /**
+     * An id for a server we haven't seen before; normally its address, unless
+     * another server already has that
+     */
+    fn new_id(&self, addr: SocketAddr) -> ServerId {
+        let mut id = ServerId(addr.to_string());
+        let mut suffix = 2;
+        while self.identities.contains_key(&id) {
+            id = ServerId(format!("{}#{}", addr, suffix));
+            suffix += 1;
+        }
+        id
+    }
+
+    fn expire(&mut self, now: SystemTime) -> bool {
+        let before = self.identities.len();
+        self.identities.retain(|_, identity| {
+            now.duration_since(identity.last_seen).unwrap_or_default() < EXPIRY
+        });
+        if self.identities.len() == before {
+            return false;
+        }
+
+        let identities = &self.identities;
+        self.by_addr.retain(|_, id| identities.contains_key(id));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const A: &str = "192.0.2.1";
+    const B: &str = "192.0.2.2";
+    const C: &str = "192.0.2.3";
+
+    /**
+     * Resolves a listing of (name, IP) pairs, returning each server's id and
+     * former names
+     */
+    fn resolve(
+        tracker: &mut IdentityTracker,
+        listing: &[(&str, &str)],
+    ) -> Vec<(String, Vec<String>)> {
+        let entries: Vec<_> = listing
+            .iter()
+            .map(|(name, ip)| json!({"NAME": name, "IP_ADDRESS": ip, "PORT": "10308"}))
+            .collect();
+        let mut servers: Servers = serde_json::from_value(json!({ "SERVERS": entries })).unwrap();
+        tracker.resolve(&mut servers);
+        servers
+            .SERVERS
+            .into_iter()
+            .map(|server| (server.id.to_string(), server.former_names))
+            .collect()
+    }
+
+    fn ids(resolved: Vec<(String, Vec<String>)>) -> Vec<String> {
+        resolved.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn keeps_ids_across_polls() {
+        let mut tracker = IdentityTracker::default();
+        let first = ids(resolve(&mut tracker, &[("Alpha", A), ("Bravo", B)]));
+        assert_eq!(first, ["192.0.2.1:10308", "192.0.2.2:10308"]);
+        assert_eq!(
+            ids(resolve(&mut tracker, &[("Bravo", B), ("Alpha", A)])),
+            ["192.0.2.2:10308", "192.0.2.1:10308"]
+        );
+    }
+
+    #[test]
+    fn follows_moves() {
+        let mut tracker = IdentityTracker::default();
+        resolve(&mut tracker, &[("Alpha", A)]);
+        assert_eq!(
+            ids(resolve(&mut tracker, &[("Alpha", B)])),
+            ["192.0.2.1:10308"]
+        );
+
+        // Found at the new address from then on, even under a new name
+        assert_eq!(
+            resolve(&mut tracker, &[("Alpha 2", B)]),
+            [("192.0.2.1:10308".to_string(), vec!["Alpha".to_string()])]
+        );
+    }
+
+    #[test]
+    fn follows_renames() {
+        let mut tracker = IdentityTracker::default();
+        resolve(&mut tracker, &[("Alpha", A)]);
+        resolve(&mut tracker, &[("Alpha Reborn", A)]);
+        assert_eq!(
+            resolve(&mut tracker, &[("Alpha Again", A)]),
+            [(
+                "192.0.2.1:10308".to_string(),
+                vec!["Alpha".to_string(), "Alpha Reborn".to_string()]
+            )]
+        );
+
+        // Going back to an old name moves it to the end
+        let resolved = resolve(&mut tracker, &[("Alpha", A)]);
+        assert_eq!(resolved[0].1, ["Alpha Reborn", "Alpha Again"]);
+    }
+
+    #[test]
+    fn follows_address_swaps() {
+        let mut tracker = IdentityTracker::default();
+        resolve(&mut tracker, &[("Alpha", A), ("Bravo", B)]);
+        let swapped = resolve(&mut tracker, &[("Alpha", B), ("Bravo", A)]);
+        assert_eq!(ids(swapped), ["192.0.2.1:10308", "192.0.2.2:10308"]);
+        assert_eq!(
+            ids(resolve(&mut tracker, &[("Bravo", A), ("Alpha", B)])),
+            ["192.0.2.2:10308", "192.0.2.1:10308"]
+        );
+    }
+
+    #[test]
+    fn gives_a_reused_address_a_new_id() {
+        let mut tracker = IdentityTracker::default();
+        resolve(&mut tracker, &[("Alpha", A)]);
+
+        // A new server takes Alpha's address as Alpha moves elsewhere
+        let resolved = ids(resolve(&mut tracker, &[("Charlie", A), ("Alpha", B)]));
+        assert_eq!(resolved, ["192.0.2.1:10308#2", "192.0.2.1:10308"]);
+        assert_eq!(
+            ids(resolve(&mut tracker, &[("Charlie", A), ("Alpha", B)])),
+            resolved
+        );
+    }
+
+    #[test]
+    fn keeps_addresses_taken_over_in_the_same_poll() {
+        let mut tracker = IdentityTracker::default();
+        resolve(&mut tracker, &[("Alpha", A), ("Bravo", B)]);
+
+        // Alpha moves to Bravo's old address before Bravo is seen to move
+        let moved = ids(resolve(&mut tracker, &[("Alpha", B), ("Bravo", C)]));
+        assert_eq!(moved, ["192.0.2.1:10308", "192.0.2.2:10308"]);
+        assert_eq!(
+            tracker
+                .by_addr
+                .get(&SocketAddr::new(B.parse().unwrap(), 10308)),
+            Some(&ServerId::from("192.0.2.1:10308".to_string()))
+        );
+
+        // So both are still found there under new names
+        assert_eq!(
+            ids(resolve(&mut tracker, &[("Alpha 2", B), ("Bravo 2", C)])),
+            moved
+        );
+    }
+}
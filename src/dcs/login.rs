@@ -0,0 +1,238 @@
+use scraper::{ElementRef, Html, Selector};
+use url::form_urlencoded;
+use url::Url;
+
+use super::DcsError;
+
+/**
+ * The DCS website's login form, filled in and ready to post
+ */
+#[derive(Debug)]
+pub struct LoginForm {
+    pub action: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl LoginForm {
+    /**
+     * Parses the login page and fills in the form with our details. The page
+     * is a Bitrix one: the form carries hidden AUTH_FORM/TYPE/backurl fields,
+     * and the session ID is either a hidden sessid field or only lives in the
+     * BX.message() script blob, depending on the template
+     */
+    pub fn parse(
+        page: &str,
+        page_url: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, DcsError> {
+        let document = Html::parse_document(page);
+        let form = find_form(&document)?;
+
+        let mut fields = vec![];
+        let mut has_password = false;
+        for input in form.select(&selector("input[name]")) {
+            let name = input.value().attr("name").unwrap_or_default();
+            let value = input.value().attr("value").unwrap_or_default();
+            let kind = input.value().attr("type").unwrap_or("text").to_lowercase();
+
+            let value = match (name, kind.as_str()) {
+                ("USER_LOGIN", _) => username,
+                ("USER_PASSWORD", _) => {
+                    has_password = true;
+                    password
+                }
+                ("USER_REMEMBER", _) => "Y",
+                (_, "hidden") | (_, "submit") => value,
+                // Anything else (captchas, etc.) we can't fill in anyway
+                _ => continue,
+            };
+            fields.push((name.to_string(), value.to_string()));
+        }
+
+        if !has_password {
+            return Err(DcsError::Parse(
+                "Login form has no USER_PASSWORD field".to_string(),
+            ));
+        }
+
+        match fields.iter_mut().find(|(name, _)| name == "sessid") {
+            Some((_, value)) if !value.is_empty() => {}
+            Some((_, value)) => *value = script_sessid(page)?,
+            None => fields.insert(0, ("sessid".to_string(), script_sessid(page)?)),
+        }
+
+        Ok(LoginForm {
+            action: resolve_action(&form, page_url)?,
+            fields,
+        })
+    }
+
+    /**
+     * The form as an application/x-www-form-urlencoded body
+     */
+    pub fn encode(&self) -> String {
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(self.fields.iter())
+            .finish()
+    }
+}
+
+/**
+ * Whether a page has the login form on it, i.e. the website wants us to log in
+ */
+pub fn is_login_page(page: &str) -> bool {
+    find_form(&Html::parse_document(page)).is_ok()
+}
+
+fn selector(selectors: &str) -> Selector {
+    Selector::parse(selectors).expect("Invalid selector")
+}
+
+/**
+ * There are other forms on the page (search, etc.); the login one is the one
+ * asking for USER_LOGIN
+ */
+fn find_form(document: &Html) -> Result<ElementRef<'_>, DcsError> {
+    let login_input = selector(r#"input[name="USER_LOGIN"]"#);
+    document
+        .select(&selector("form"))
+        .find(|form| form.select(&login_input).next().is_some())
+        .ok_or_else(|| {
+            DcsError::Parse("Login form (with a USER_LOGIN field) not found".to_string())
+        })
+}
+
+/**
+ * Where the form posts to; an empty or missing action means the page itself
+ */
+fn resolve_action(form: &ElementRef, page_url: &str) -> Result<String, DcsError> {
+    let base = Url::parse(page_url)
+        .map_err(|err| DcsError::Parse(format!("Bad login page URL {}: {}", page_url, err)))?;
+    match form.value().attr("action").map(str::trim) {
+        None | Some("") => Ok(base.to_string()),
+        Some(action) => base
+            .join(action)
+            .map(|url| url.to_string())
+            .map_err(|err| DcsError::Parse(format!("Bad login form action {}: {}", action, err))),
+    }
+}
+
+/**
+ * Pulls bitrix_sessid out of BX.message({...,'bitrix_sessid':'abc123'}),
+ * whichever quotes the template happens to use
+ */
+fn script_sessid(page: &str) -> Result<String, DcsError> {
+    let missing =
+        || DcsError::Parse("Session ID (sessid/bitrix_sessid) not found on login page".to_string());
+
+    let start = page.find("bitrix_sessid").ok_or_else(missing)? + "bitrix_sessid".len();
+    let rest =
+        page[start..].trim_start_matches(|c: char| c == '"' || c == '\'' || c.is_whitespace());
+    let rest = rest.strip_prefix(':').ok_or_else(missing)?.trim_start();
+
+    let quote = rest
+        .chars()
+        .next()
+        .filter(|c| *c == '"' || *c == '\'')
+        .ok_or_else(missing)?;
+    let value = &rest[1..];
+    let end = value.find(quote).ok_or_else(missing)?;
+
+    match &value[..end] {
+        "" => Err(missing()),
+        sessid => Ok(sessid.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_URL: &str = "https://www.digitalcombatsimulator.com/en/personal/profile/?login=yes";
+
+    fn field<'a>(form: &'a LoginForm, name: &str) -> Option<&'a str> {
+        form.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn parses_login_form() {
+        let page = include_str!("../../tests/fixtures/login_page.html");
+        let form = LoginForm::parse(page, PAGE_URL, "pilot", "hunter2").unwrap();
+
+        assert_eq!(form.action, PAGE_URL);
+        assert_eq!(
+            field(&form, "sessid"),
+            Some("3f8e2c1a9b7d6e5f4a3b2c1d0e9f8a7b")
+        );
+        assert_eq!(field(&form, "AUTH_FORM"), Some("Y"));
+        assert_eq!(field(&form, "TYPE"), Some("AUTH"));
+        assert_eq!(field(&form, "backurl"), Some("/en/personal/profile/"));
+        assert_eq!(field(&form, "USER_LOGIN"), Some("pilot"));
+        assert_eq!(field(&form, "USER_PASSWORD"), Some("hunter2"));
+        assert_eq!(field(&form, "USER_REMEMBER"), Some("Y"));
+        assert_eq!(field(&form, "Login"), Some("Log In"));
+
+        // Not from the search form
+        assert_eq!(field(&form, "q"), None);
+    }
+
+    #[test]
+    fn falls_back_to_script_sessid() {
+        let page = include_str!("../../tests/fixtures/login_page_script_sessid.html");
+        let form = LoginForm::parse(page, PAGE_URL, "pilot", "hunter2").unwrap();
+
+        assert_eq!(form.action, PAGE_URL);
+        assert_eq!(
+            field(&form, "sessid"),
+            Some("c0ffee00c0ffee00c0ffee00c0ffee00")
+        );
+        assert_eq!(field(&form, "backurl"), Some("/en/"));
+    }
+
+    #[test]
+    fn encodes_awkward_passwords() {
+        let page = include_str!("../../tests/fixtures/login_page.html");
+        let form = LoginForm::parse(page, PAGE_URL, "pilot one", "p&ss=word+%").unwrap();
+        let body = form.encode();
+
+        assert!(body.contains("USER_LOGIN=pilot+one"));
+        assert!(body.contains("USER_PASSWORD=p%26ss%3Dword%2B%25"));
+        assert!(body.contains("backurl=%2Fen%2Fpersonal%2Fprofile%2F"));
+    }
+
+    #[test]
+    fn reports_missing_form() {
+        let page = include_str!("../../tests/fixtures/maintenance_page.html");
+        let err = LoginForm::parse(page, PAGE_URL, "pilot", "hunter2").unwrap_err();
+        assert!(err.to_string().contains("Login form"), "{}", err);
+    }
+
+    #[test]
+    fn spots_login_pages() {
+        assert!(is_login_page(include_str!(
+            "../../tests/fixtures/login_page.html"
+        )));
+        assert!(!is_login_page(include_str!(
+            "../../tests/fixtures/maintenance_page.html"
+        )));
+    }
+
+    #[test]
+    fn reports_missing_password_field() {
+        let page = r#"<form><input type="hidden" name="sessid" value="abc"><input name="USER_LOGIN"></form>"#;
+        let err = LoginForm::parse(page, PAGE_URL, "pilot", "hunter2").unwrap_err();
+        assert!(err.to_string().contains("USER_PASSWORD"), "{}", err);
+    }
+
+    #[test]
+    fn reports_missing_sessid() {
+        let page =
+            r#"<form><input name="USER_LOGIN"><input type="password" name="USER_PASSWORD"></form>"#;
+        let err = LoginForm::parse(page, PAGE_URL, "pilot", "hunter2").unwrap_err();
+        assert!(err.to_string().contains("Session ID"), "{}", err);
+    }
+}
@@ -0,0 +1,366 @@
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{DcsVersion, ServerId};
+
+/**
+ * Structs for serde to be able to deserialize the json
+ *
+ * The website serves almost everything as strings ("PORT": "10308"), and the
+ * odd value is blank or padded, so every typed field goes through one of the
+ * lenient deserializers below. Missing or empty values become the default;
+ * anything that can't be made sense of fails only the server it belongs to.
+ */
+#[derive(Deserialize, Clone, Debug)]
+#[allow(non_snake_case, dead_code)] // Mirrors the listing; not every field is displayed
+pub struct Server {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub NAME: String,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub MISSION_NAME: String,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub PLAYERS: u32,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub PLAYERS_MAX: u32,
+
+    #[serde(default = "unspecified_ip", deserialize_with = "lenient_ip")]
+    pub IP_ADDRESS: IpAddr,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub PORT: u16,
+
+    #[serde(default, deserialize_with = "lenient_version")]
+    pub DCS_VERSION: DcsVersion,
+    #[serde(default, deserialize_with = "lenient_duration")]
+    pub MISSION_TIME: Duration,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub MISSION_TIME_FORMATTED: String,
+    #[serde(default, deserialize_with = "lenient_bool")]
+    pub PASSWORD: bool,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub DESCRIPTION: String,
+
+    // Not part of the listing; filled in by IdentityTracker
+    #[serde(skip)]
+    pub id: ServerId,
+    #[serde(skip)]
+    pub former_names: Vec<String>,
+}
+
+/**
+ * A snapshot of the listing. Entries that fail to deserialize or validate are
+ * quarantined (logged with their raw JSON) rather than failing the snapshot;
+ * `rejected` counts how many were dropped from this poll
+ */
+#[derive(Deserialize, Clone, Debug)]
+#[serde(from = "RawServers")]
+#[allow(non_snake_case, dead_code)]
+pub struct Servers {
+    pub SERVERS: Vec<Server>,
+    pub SERVERS_MAX_COUNT: u32,
+    pub SERVERS_MAX_DATE: String,
+    pub PLAYERS_COUNT: u32,
+    pub MY_SERVERS: Vec<Server>,
+    pub rejected: usize,
+}
+
+/**
+ * Servers as they arrive, before each entry has been checked
+ */
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct RawServers {
+    #[serde(default, deserialize_with = "entries")]
+    SERVERS: Vec<Value>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    SERVERS_MAX_COUNT: u32,
+    #[serde(default, deserialize_with = "lenient_string")]
+    SERVERS_MAX_DATE: String,
+    #[serde(default, deserialize_with = "lenient_number")]
+    PLAYERS_COUNT: u32,
+    #[serde(default, deserialize_with = "entries")]
+    MY_SERVERS: Vec<Value>,
+}
+
+impl From<RawServers> for Servers {
+    fn from(raw: RawServers) -> Self {
+        let mut rejected = 0;
+        let servers = quarantine(raw.SERVERS, &mut rejected);
+        let my_servers = quarantine(raw.MY_SERVERS, &mut rejected);
+
+        if rejected > 0 {
+            println!("\x1b[31m{} server entries rejected\x1b[0m", rejected);
+        }
+
+        Servers {
+            SERVERS: servers,
+            SERVERS_MAX_COUNT: raw.SERVERS_MAX_COUNT,
+            SERVERS_MAX_DATE: raw.SERVERS_MAX_DATE,
+            PLAYERS_COUNT: raw.PLAYERS_COUNT,
+            MY_SERVERS: my_servers,
+            rejected,
+        }
+    }
+}
+
+impl Server {
+    /**
+     * Checks the things the rest of the bot relies on; deserializing only
+     * guarantees the types are right
+     */
+    fn validate(&self) -> Result<(), String> {
+        if self.NAME.trim().is_empty() {
+            return Err("missing NAME".to_string());
+        }
+        if self.IP_ADDRESS.is_unspecified() {
+            return Err("missing IP_ADDRESS".to_string());
+        }
+        if self.PORT == 0 {
+            return Err("missing PORT".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn unspecified_ip() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+/**
+ * Turns a JSON scalar into trimmed text, treating null as empty
+ */
+fn scalar_text<E: de::Error>(value: &Value) -> Result<String, E> {
+    match value {
+        Value::Null => Ok(String::new()),
+        Value::String(s) => Ok(s.trim().to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(E::custom(format!("expected a scalar, got {}", value))),
+    }
+}
+
+fn lenient_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    match value {
+        // Don't trim real strings; the name sanitizer deals with whitespace
+        Value::String(s) => Ok(s),
+        value => scalar_text(&value),
+    }
+}
+
+/**
+ * Accepts 12, "12", " 12 ", "12.0" and "" (as 0)
+ */
+fn lenient_number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + Default + TryFrom<u64>,
+{
+    let text = scalar_text::<D::Error>(&Value::deserialize(deserializer)?)?;
+    if text.is_empty() {
+        return Ok(T::default());
+    }
+    if let Ok(number) = text.parse::<T>() {
+        return Ok(number);
+    }
+
+    // Whole numbers that arrived as floats
+    match text.parse::<f64>() {
+        Ok(float) if float >= 0.0 && float.fract() == 0.0 => T::try_from(float as u64)
+            .map_err(|_| de::Error::custom(format!("number out of range: {}", text))),
+        _ => Err(de::Error::custom(format!("not a number: {:?}", text))),
+    }
+}
+
+fn lenient_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DcsVersion, D::Error> {
+    let text = scalar_text::<D::Error>(&Value::deserialize(deserializer)?)?;
+    Ok(DcsVersion::parse_lenient(&text))
+}
+
+fn lenient_ip<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpAddr, D::Error> {
+    let text = scalar_text::<D::Error>(&Value::deserialize(deserializer)?)?;
+    if text.is_empty() {
+        return Ok(unspecified_ip());
+    }
+    text.parse::<IpAddr>()
+        .map_err(|_| de::Error::custom(format!("not an IP address: {:?}", text)))
+}
+
+fn lenient_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let text = scalar_text::<D::Error>(&Value::deserialize(deserializer)?)?;
+    match text.to_lowercase().as_str() {
+        "" | "0" | "n" | "no" | "false" | "off" => Ok(false),
+        "1" | "y" | "yes" | "true" | "on" => Ok(true),
+        _ => Err(de::Error::custom(format!("not a boolean: {:?}", text))),
+    }
+}
+
+/**
+ * MISSION_TIME is the number of seconds the mission has been running
+ */
+fn lenient_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = scalar_text::<D::Error>(&Value::deserialize(deserializer)?)?;
+    if text.is_empty() {
+        return Ok(Duration::ZERO);
+    }
+    match text.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(de::Error::custom(format!("not a duration: {:?}", text))),
+    }
+}
+
+/**
+ * Accepts a list (or null, as an empty list) without looking inside it
+ */
+fn entries<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Value>, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Array(entries) => Ok(entries),
+        Value::Null => Ok(vec![]),
+        value => Err(de::Error::custom(format!(
+            "expected a list of servers, got {}",
+            value
+        ))),
+    }
+}
+
+/**
+ * Deserializes and validates each server on its own so that one bad entry is
+ * set aside rather than failing the whole snapshot
+ */
+fn quarantine(entries: Vec<Value>, rejected: &mut usize) -> Vec<Server> {
+    let mut servers = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let result = Server::deserialize(&entry)
+            .map_err(|err| err.to_string())
+            .and_then(|server| server.validate().map(|_| server));
+
+        match result {
+            Ok(server) => servers.push(server),
+            Err(reason) => {
+                *rejected += 1;
+                println!(
+                    "\x1b[31mQuarantined server entry ({}): {}\x1b[0m",
+                    reason, entry
+                );
+            }
+        }
+    }
+    servers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn server(fields: Value) -> Result<Server, serde_json::Error> {
+        let mut entry = json!({
+            "NAME": "Test Server",
+            "IP_ADDRESS": "192.0.2.1",
+            "PORT": "10308",
+        });
+        for (key, value) in fields.as_object().unwrap() {
+            entry[key] = value.clone();
+        }
+        Server::deserialize(&entry)
+    }
+
+    #[test]
+    fn reads_numbers_sent_as_strings() {
+        let s = server(json!({"PLAYERS": "12", "PLAYERS_MAX": " 32 ", "PORT": 10309})).unwrap();
+        assert_eq!(s.PLAYERS, 12);
+        assert_eq!(s.PLAYERS_MAX, 32);
+        assert_eq!(s.PORT, 10309);
+    }
+
+    #[test]
+    fn reads_whole_floats_as_numbers() {
+        assert_eq!(server(json!({"PLAYERS": "12.0"})).unwrap().PLAYERS, 12);
+        assert_eq!(server(json!({"PLAYERS": 7.0})).unwrap().PLAYERS, 7);
+        assert!(server(json!({"PLAYERS": "12.5"})).is_err());
+        assert!(server(json!({"PLAYERS": "-1"})).is_err());
+        assert!(server(json!({"PORT": "70000"})).is_err());
+    }
+
+    #[test]
+    fn treats_empty_and_missing_as_default() {
+        let s = server(json!({
+            "PLAYERS": "",
+            "PLAYERS_MAX": null,
+            "MISSION_TIME": "",
+            "PASSWORD": "",
+        }))
+        .unwrap();
+        assert_eq!(s.PLAYERS, 0);
+        assert_eq!(s.PLAYERS_MAX, 0);
+        assert_eq!(s.MISSION_TIME, Duration::ZERO);
+        assert!(!s.PASSWORD);
+        assert_eq!(s.MISSION_NAME, "");
+        assert_eq!(s.DCS_VERSION, DcsVersion::default());
+    }
+
+    #[test]
+    fn reads_booleans_and_durations() {
+        let s = server(json!({"PASSWORD": "Yes", "MISSION_TIME": "90.5"})).unwrap();
+        assert!(s.PASSWORD);
+        assert_eq!(s.MISSION_TIME, Duration::from_secs_f64(90.5));
+        assert!(!server(json!({"PASSWORD": 0})).unwrap().PASSWORD);
+        assert!(server(json!({"PASSWORD": "maybe"})).is_err());
+        assert!(server(json!({"MISSION_TIME": "-5"})).is_err());
+    }
+
+    #[test]
+    fn reads_ip_addresses() {
+        let s = server(json!({"IP_ADDRESS": " 2001:db8::1 "})).unwrap();
+        assert_eq!(s.IP_ADDRESS, "2001:db8::1".parse::<IpAddr>().unwrap());
+
+        let err = server(json!({"IP_ADDRESS": "300.1.2.3"})).unwrap_err();
+        assert!(err.to_string().contains("not an IP address"), "{}", err);
+        assert!(server(json!({"IP_ADDRESS": "example.com"})).is_err());
+    }
+
+    #[test]
+    fn keeps_strings_as_sent() {
+        let s = server(json!({"NAME": "  Padded  ", "MISSION_NAME": 42})).unwrap();
+        assert_eq!(s.NAME, "  Padded  ");
+        assert_eq!(s.MISSION_NAME, "42");
+        assert!(server(json!({"NAME": ["a list"]})).is_err());
+    }
+
+    #[test]
+    fn quarantines_bad_entries() {
+        let servers: Servers = serde_json::from_value(json!({
+            "SERVERS": [
+                {"NAME": "Good One", "IP_ADDRESS": "192.0.2.1", "PORT": "10308", "PLAYERS": "3"},
+                {"NAME": "Bad Players", "IP_ADDRESS": "192.0.2.2", "PORT": "10308", "PLAYERS": "lots"},
+                {"NAME": "No Address", "PORT": "10308"},
+                "not even an object",
+                {"NAME": "Good Two", "IP_ADDRESS": "192.0.2.3", "PORT": 10309},
+            ],
+            "MY_SERVERS": [
+                {"NAME": "", "IP_ADDRESS": "192.0.2.4", "PORT": "10308"},
+            ],
+            "PLAYERS_COUNT": "5",
+        }))
+        .unwrap();
+
+        let names: Vec<&str> = servers.SERVERS.iter().map(|s| s.NAME.as_str()).collect();
+        assert_eq!(names, ["Good One", "Good Two"]);
+        assert_eq!(servers.SERVERS[0].PLAYERS, 3);
+        assert!(servers.MY_SERVERS.is_empty());
+        assert_eq!(servers.PLAYERS_COUNT, 5);
+        assert_eq!(servers.rejected, 4);
+    }
+
+    #[test]
+    fn rejects_a_listing_that_is_not_a_list() {
+        assert!(serde_json::from_value::<Servers>(json!({"SERVERS": "none"})).is_err());
+
+        let servers: Servers = serde_json::from_value(json!({"SERVERS": null})).unwrap();
+        assert!(servers.SERVERS.is_empty());
+        assert_eq!(servers.rejected, 0);
+    }
+}
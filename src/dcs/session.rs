@@ -0,0 +1,583 @@
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Response, StatusCode, Url};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::login::{is_login_page, LoginForm};
+use super::{DcsError, Endpoints, Servers};
+
+// How many times a network error is retried in place before giving up
+const NETWORK_RETRIES: u32 = 3;
+const NETWORK_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/**
+ * A logged-in session with the DCS website. Holds the cookie jar across polls
+ * and logs in again by itself when the session expires, while network errors
+ * are retried without throwing the session away
+ */
+pub struct DcsSession {
+    client: Client,
+    jar: Arc<Jar>,
+    endpoints: Endpoints,
+    username: String,
+    password: String,
+    logged_in: bool,
+    retry_delay: Duration,
+}
+
+/**
+ * Spots the website asking us to slow down (HTTP 429 or 503), along with how
+ * long it wants us to wait if it sent a Retry-After header in seconds
+ */
+pub fn rate_limit(status: StatusCode, headers: &HeaderMap) -> Option<DcsError> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+
+    let retry_after = headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+    Some(DcsError::RateLimited(retry_after))
+}
+
+/**
+ * Works out whether a response is the server list, or the website telling us
+ * (in one of its many ways) that we're not logged in any more. Any other page
+ * (e.g. maintenance) is a parse error, as logging in again won't get us past it
+ */
+fn classify(
+    requested: &Url,
+    final_url: &Url,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &str,
+) -> Result<(), DcsError> {
+    if requested != final_url {
+        return Err(DcsError::SessionExpired(format!(
+            "redirected to {}",
+            final_url
+        )));
+    }
+
+    if let Some(err) = rate_limit(status, headers) {
+        return Err(err);
+    }
+
+    if status.is_server_error() {
+        return Err(DcsError::Network(format!("HTTP {}", status)));
+    }
+
+    let is_html = headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/html"));
+
+    if is_html || !body.trim_start().starts_with('{') {
+        if is_login_page(body) {
+            return Err(DcsError::SessionExpired(
+                "got the login page instead of JSON".to_string(),
+            ));
+        }
+        return Err(DcsError::Parse(
+            "got a page instead of JSON (the website may be down for maintenance)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn client_with(jar: Arc<Jar>) -> Client {
+    Client::builder()
+        .cookie_provider(jar)
+        .build()
+        .expect("Error creating HTTP client")
+}
+
+impl DcsSession {
+    pub fn new(endpoints: Endpoints, username: String, password: String) -> Self {
+        let jar = Arc::new(Jar::default());
+        let client = client_with(jar.clone());
+
+        DcsSession {
+            client,
+            jar,
+            endpoints,
+            username,
+            password,
+            logged_in: false,
+            retry_delay: NETWORK_RETRY_DELAY,
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    fn base_url(&self) -> Option<Url> {
+        Url::parse(&self.endpoints.base_url).ok()
+    }
+
+    /**
+     * The website sets DCS_UIDD once we're logged in, and drops it when the
+     * login expires
+     */
+    fn has_auth_cookie(&self) -> bool {
+        let url = match self.base_url() {
+            Some(url) => url,
+            None => return false,
+        };
+        match self.jar.cookies(&url) {
+            Some(cookies) => cookies
+                .to_str()
+                .unwrap_or_default()
+                .split(';')
+                .any(|cookie| cookie.trim_start().starts_with("DCS_UIDD=")),
+            None => false,
+        }
+    }
+
+    /**
+     * Logs in to the DCS website, leaving the auth cookies in our jar. Each
+     * login starts with an empty jar, so a DCS_UIDD left over from an earlier
+     * session can't make a rejected login look like it worked
+     */
+    async fn login(&mut self) -> Result<(), DcsError> {
+        println!("Logging in");
+        self.logged_in = false;
+        self.jar = Arc::new(Jar::default());
+        self.client = client_with(self.jar.clone());
+
+        if self.username.is_empty() || self.password.is_empty() {
+            return Err(DcsError::Auth("No username or password".to_string()));
+        }
+
+        let res = self
+            .client
+            .get(self.endpoints.login_url())
+            .timeout(Duration::from_secs(60))
+            .send()
+            .await?;
+
+        if let Some(err) = rate_limit(res.status(), res.headers()) {
+            return Err(err);
+        }
+
+        let page_url = res.url().to_string();
+        let page = res.text().await?;
+        let form = LoginForm::parse(&page, &page_url, &self.username, &self.password)?;
+
+        let login_result = self
+            .client
+            .post(&form.action)
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(form.encode())
+            .timeout(Duration::from_secs(60))
+            .send()
+            .await?;
+
+        if let Some(err) = rate_limit(login_result.status(), login_result.headers()) {
+            return Err(err);
+        }
+
+        if !self.has_auth_cookie() {
+            return Err(DcsError::Auth("username/password incorrect".to_string()));
+        }
+
+        if let Some(url) = self.base_url() {
+            self.jar.add_cookie_str("DCS_SOUND_LOGIN_PLAYED=Y", &url);
+            self.jar.add_cookie_str("DCS_SALE_UID=0", &url);
+        }
+
+        println!("Logged in");
+        self.logged_in = true;
+        Ok(())
+    }
+
+    async fn read_servers(&self, res: Response) -> Result<Servers, DcsError> {
+        let requested = Url::parse(&self.endpoints.servers_url())
+            .map_err(|err| DcsError::Parse(format!("Bad servers URL: {:?}", err)))?;
+        let final_url = res.url().clone();
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = res.text().await?;
+        classify(&requested, &final_url, status, &headers, &body)?;
+
+        serde_json::from_str::<Servers>(&body)
+            .map_err(|err| DcsError::Parse(format!("JSON parse error: {:?}", err)))
+    }
+
+    async fn fetch_servers(&self) -> Result<Servers, DcsError> {
+        let res = self
+            .client
+            .get(self.endpoints.servers_url())
+            .header("content-type", "application/x-www-form-urlencoded")
+            .timeout(Duration::from_secs(120))
+            .send()
+            .await?;
+
+        self.read_servers(res).await
+    }
+
+    /**
+     * Gets the current list of servers from the DCS website, logging in first
+     * if we need to. An expired session gets one fresh login per call; network
+     * errors are retried a few times with the session left as it is
+     */
+    pub async fn get_servers(&mut self) -> Result<Servers, DcsError> {
+        println!("Getting servers");
+        let mut relogged = false;
+        let mut network_failures = 0;
+
+        loop {
+            if !self.logged_in || !self.has_auth_cookie() {
+                self.login().await?;
+            }
+
+            match self.fetch_servers().await {
+                Ok(servers) => return Ok(servers),
+                Err(DcsError::SessionExpired(reason)) => {
+                    println!("\x1b[33mSession expired ({})\x1b[0m", reason);
+                    self.logged_in = false;
+                    if relogged {
+                        return Err(DcsError::SessionExpired(reason));
+                    }
+                    relogged = true;
+                }
+                Err(DcsError::Network(reason)) => {
+                    network_failures += 1;
+                    if network_failures > NETWORK_RETRIES {
+                        return Err(DcsError::Network(reason));
+                    }
+                    println!(
+                        "\x1b[33mNetwork error ({}), retrying ({}/{})\x1b[0m",
+                        reason, network_failures, NETWORK_RETRIES
+                    );
+                    tokio::time::sleep(self.retry_delay * network_failures).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, CONTENT_TYPE, RETRY_AFTER};
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const LOGIN_PAGE: &str = include_str!("../../tests/fixtures/login_page.html");
+    const MAINTENANCE_PAGE: &str = include_str!("../../tests/fixtures/maintenance_page.html");
+    const LISTING: &str =
+        r#"{"SERVERS": [{"NAME": "Alpha", "IP_ADDRESS": "192.0.2.1", "PORT": "10308"}]}"#;
+
+    fn servers_url() -> Url {
+        Url::parse(&Endpoints::default().servers_url()).unwrap()
+    }
+
+    fn headers(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        headers
+    }
+
+    fn classify_page(status: StatusCode, headers: &HeaderMap, body: &str) -> Result<(), DcsError> {
+        classify(&servers_url(), &servers_url(), status, headers, body)
+    }
+
+    #[test]
+    fn accepts_the_listing() {
+        let json = headers("application/json");
+        assert!(classify_page(StatusCode::OK, &json, LISTING).is_ok());
+        // Sometimes served as text/plain, which is still fine
+        assert!(classify_page(StatusCode::OK, &headers("text/plain"), LISTING).is_ok());
+    }
+
+    #[test]
+    fn spots_expired_sessions() {
+        let login_url = Url::parse(&Endpoints::default().login_url()).unwrap();
+        let redirected = classify(
+            &servers_url(),
+            &login_url,
+            StatusCode::OK,
+            &headers("text/html"),
+            LOGIN_PAGE,
+        );
+        assert!(matches!(redirected, Err(DcsError::SessionExpired(_))));
+
+        let html = headers("text/html; charset=UTF-8");
+        assert!(matches!(
+            classify_page(StatusCode::OK, &html, LOGIN_PAGE),
+            Err(DcsError::SessionExpired(_))
+        ));
+        // Even when it isn't labelled as HTML
+        assert!(matches!(
+            classify_page(StatusCode::OK, &headers("application/json"), LOGIN_PAGE),
+            Err(DcsError::SessionExpired(_))
+        ));
+    }
+
+    #[test]
+    fn does_not_log_in_again_for_other_pages() {
+        let html = headers("text/html");
+        assert!(matches!(
+            classify_page(StatusCode::OK, &html, MAINTENANCE_PAGE),
+            Err(DcsError::Parse(_))
+        ));
+        assert!(matches!(
+            classify_page(StatusCode::BAD_GATEWAY, &html, MAINTENANCE_PAGE),
+            Err(DcsError::Network(_))
+        ));
+        assert!(matches!(
+            classify_page(StatusCode::INTERNAL_SERVER_ERROR, &HeaderMap::new(), ""),
+            Err(DcsError::Network(_))
+        ));
+    }
+
+    #[test]
+    fn reads_rate_limits() {
+        let mut limited = headers("text/html");
+        limited.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert!(matches!(
+            classify_page(StatusCode::TOO_MANY_REQUESTS, &limited, ""),
+            Err(DcsError::RateLimited(Some(wait))) if wait == Duration::from_secs(120)
+        ));
+        assert!(matches!(
+            classify_page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &headers("text/html"),
+                MAINTENANCE_PAGE
+            ),
+            Err(DcsError::RateLimited(None))
+        ));
+
+        // Only a number of seconds is understood
+        limited.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert!(matches!(
+            classify_page(StatusCode::TOO_MANY_REQUESTS, &limited, ""),
+            Err(DcsError::RateLimited(None))
+        ));
+    }
+
+    #[test]
+    fn needs_the_dcs_uidd_cookie() {
+        let session = DcsSession::new(Endpoints::default(), String::new(), String::new());
+        let url = session.base_url().unwrap();
+        assert!(!session.has_auth_cookie());
+
+        session.jar.add_cookie_str("DCS_SALE_UID=0", &url);
+        session.jar.add_cookie_str("OLD_DCS_UIDD=1", &url);
+        assert!(!session.has_auth_cookie());
+
+        session.jar.add_cookie_str("DCS_UIDD=1", &url);
+        assert!(session.has_auth_cookie());
+    }
+
+    /**
+     * A stand-in for the DCS website on a local port. It serves the login
+     * page, accepts the first `accepted_logins` logins (setting DCS_UIDD) and
+     * answers each request for the server list with the next of `listings`
+     * (status, content type and body). Counts the logins it accepted
+     */
+    async fn website(
+        accepted_logins: usize,
+        listings: Vec<(u16, &'static str, &'static str)>,
+    ) -> (Endpoints, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoints = Endpoints {
+            base_url: format!("http://{}", listener.local_addr().unwrap()),
+            ..Endpoints::default()
+        };
+        let logins = Arc::new(AtomicUsize::new(0));
+        let listings = Arc::new(Mutex::new(VecDeque::from(listings)));
+
+        let counter = logins.clone();
+        let paths = endpoints.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let request = read_request(&mut stream).await;
+                let (method, path) = request.split_once(' ').unwrap_or_default();
+                let path = path.split(' ').next().unwrap_or_default();
+
+                let (status, content_type, body, cookie) = if path == paths.login_path {
+                    if method == "GET" {
+                        (200, "text/html", LOGIN_PAGE, None)
+                    } else if counter.load(Ordering::SeqCst) < accepted_logins {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        (200, "text/html", "Welcome", Some("DCS_UIDD=1; Path=/"))
+                    } else {
+                        (200, "text/html", LOGIN_PAGE, None)
+                    }
+                } else if path == paths.servers_path {
+                    match listings.lock().unwrap().pop_front() {
+                        Some((status, content_type, body)) => (status, content_type, body, None),
+                        None => (404, "text/plain", "", None),
+                    }
+                } else {
+                    (404, "text/plain", "", None)
+                };
+
+                let cookie = cookie
+                    .map(|cookie| format!("Set-Cookie: {}\r\n", cookie))
+                    .unwrap_or_default();
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    cookie,
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        (endpoints, logins)
+    }
+
+    /**
+     * Reads a request's head and body, returning the request line
+     */
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> String {
+        let mut request = vec![];
+        let mut buffer = [0; 4096];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap_or(0);
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+
+            let text = String::from_utf8_lossy(&request);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length = text[..end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if request.len() >= end + 4 + length {
+                    break;
+                }
+            }
+        }
+        String::from_utf8_lossy(&request)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn session(endpoints: Endpoints) -> DcsSession {
+        let mut session = DcsSession::new(endpoints, "user".to_string(), "secret".to_string());
+        session.retry_delay = Duration::from_millis(1);
+        session
+    }
+
+    #[tokio::test]
+    async fn logs_in_and_gets_servers() {
+        let (endpoints, logins) = website(1, vec![(200, "application/json", LISTING)]).await;
+        let mut session = session(endpoints);
+        let servers = session.get_servers().await.unwrap();
+        assert_eq!(servers.SERVERS[0].NAME, "Alpha");
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_once_per_call() {
+        let (endpoints, logins) = website(
+            3,
+            vec![
+                (200, "text/html", LOGIN_PAGE),
+                (200, "application/json", LISTING),
+                (200, "text/html", LOGIN_PAGE),
+                (200, "text/html", LOGIN_PAGE),
+            ],
+        )
+        .await;
+        let mut session = session(endpoints);
+        assert!(session.get_servers().await.is_ok());
+        assert_eq!(logins.load(Ordering::SeqCst), 2);
+
+        // Expiring straight after a fresh login gives up rather than looping
+        assert!(matches!(
+            session.get_servers().await,
+            Err(DcsError::SessionExpired(_))
+        ));
+        assert_eq!(logins.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_login_despite_an_old_cookie() {
+        let (endpoints, logins) = website(1, vec![(200, "text/html", LOGIN_PAGE)]).await;
+        let mut session = session(endpoints);
+        assert!(matches!(
+            session.get_servers().await,
+            Err(DcsError::Auth(_))
+        ));
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_network_errors_without_logging_in_again() {
+        let (endpoints, logins) = website(
+            1,
+            vec![
+                (500, "text/html", ""),
+                (502, "text/html", MAINTENANCE_PAGE),
+                (200, "application/json", LISTING),
+                (500, "text/html", ""),
+                (500, "text/html", ""),
+                (500, "text/html", ""),
+                (500, "text/html", ""),
+                (200, "application/json", LISTING),
+            ],
+        )
+        .await;
+        let mut session = session(endpoints);
+        assert!(session.get_servers().await.is_ok());
+
+        // Gives up after NETWORK_RETRIES retries, leaving the last listing
+        assert!(matches!(
+            session.get_servers().await,
+            Err(DcsError::Network(_))
+        ));
+        assert!(session.get_servers().await.is_ok());
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_log_in_again_for_maintenance() {
+        let (endpoints, logins) = website(
+            1,
+            vec![
+                (200, "text/html", MAINTENANCE_PAGE),
+                (200, "application/json", LISTING),
+            ],
+        )
+        .await;
+        let mut session = session(endpoints);
+        assert!(matches!(
+            session.get_servers().await,
+            Err(DcsError::Parse(_))
+        ));
+        assert!(session.get_servers().await.is_ok());
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+    }
+}
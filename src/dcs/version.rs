@@ -0,0 +1,268 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/**
+ * An ED version number like 2.9.3.51704 (or the older 5-part
+ * 2.5.6.61527.1), compared part by part as numbers so 2.9.10 sorts after
+ * 2.9.9. Strings that don't parse are kept as-is and sort before everything
+ * else
+ */
+#[derive(Clone, Debug, Default)]
+pub struct DcsVersion {
+    parts: Vec<u32>,
+    raw: String,
+}
+
+impl DcsVersion {
+    /**
+     * Never fails; for version strings from the server listing, where one odd
+     * value shouldn't cost us the server
+     */
+    pub fn parse_lenient(text: &str) -> Self {
+        text.parse().unwrap_or_else(|_| DcsVersion {
+            parts: vec![],
+            raw: text.trim().to_string(),
+        })
+    }
+
+    pub fn is_known(&self) -> bool {
+        !self.parts.is_empty()
+    }
+
+    /**
+     * Compares only as many parts as `other` has, so 2.9.3.51704 is equal to
+     * 2.9 and greater than 2.8
+     */
+    pub fn cmp_prefix(&self, other: &DcsVersion) -> Ordering {
+        let len = self.parts.len().min(other.parts.len());
+        self.parts[..len].cmp(&other.parts)
+    }
+}
+
+impl FromStr for DcsVersion {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let raw = text.trim();
+        let digits = raw.trim_start_matches(['v', 'V']);
+        if digits.is_empty() {
+            return Err(format!("Not a version: {:?}", text));
+        }
+
+        let parts = digits
+            .split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| format!("Not a version: {:?}", text))?;
+
+        if parts.len() > 5 {
+            return Err(format!("Too many parts in version: {:?}", text));
+        }
+
+        Ok(DcsVersion {
+            parts,
+            raw: raw.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for DcsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+impl PartialEq for DcsVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for DcsVersion {}
+
+impl Hash for DcsVersion {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.parts.hash(state);
+        if self.parts.is_empty() {
+            self.raw.hash(state);
+        }
+    }
+}
+
+impl PartialOrd for DcsVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DcsVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Unknown versions only equal each other if the text matches
+        match (self.parts.is_empty(), other.parts.is_empty()) {
+            (true, true) => self.raw.cmp(&other.raw),
+            _ => self.parts.cmp(&other.parts),
+        }
+    }
+}
+
+impl Serialize for DcsVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for DcsVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/**
+ * A version constraint like ">=2.9" or "version < 2.8.8", compared to the
+ * precision it was written with (see cmp_prefix)
+ */
+#[derive(Clone, Debug)]
+pub struct VersionReq {
+    op: Ordering,
+    or_equal: bool,
+    negate: bool,
+    version: DcsVersion,
+}
+
+impl VersionReq {
+    pub fn matches(&self, version: &DcsVersion) -> bool {
+        if !version.is_known() {
+            return false;
+        }
+        let ordering = version.cmp_prefix(&self.version);
+        let result = ordering == self.op || (self.or_equal && ordering == Ordering::Equal);
+        result != self.negate
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let text = text
+            .strip_prefix("version")
+            .or_else(|| text.strip_prefix("Version"))
+            .unwrap_or(text)
+            .trim_start();
+
+        // Longest operators first
+        let (op, or_equal, negate, rest) = if let Some(rest) = text.strip_prefix(">=") {
+            (Ordering::Greater, true, false, rest)
+        } else if let Some(rest) = text.strip_prefix("<=") {
+            (Ordering::Less, true, false, rest)
+        } else if let Some(rest) = text.strip_prefix("!=") {
+            (Ordering::Equal, false, true, rest)
+        } else if let Some(rest) = text.strip_prefix('>') {
+            (Ordering::Greater, false, false, rest)
+        } else if let Some(rest) = text.strip_prefix('<') {
+            (Ordering::Less, false, false, rest)
+        } else if let Some(rest) = text.strip_prefix('=') {
+            (Ordering::Equal, false, false, rest.trim_start_matches('='))
+        } else {
+            return Err(format!("Not a version comparison: {:?}", text));
+        };
+
+        Ok(VersionReq {
+            op,
+            or_equal,
+            negate,
+            version: rest.parse()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> DcsVersion {
+        DcsVersion::parse_lenient(text)
+    }
+
+    fn req(text: &str) -> VersionReq {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn orders_parts_as_numbers() {
+        assert!(version("2.9.10") > version("2.9.9"));
+        assert!(version("2.9.10.4160") > version("2.9.9.99999"));
+        assert!(version("2.10") > version("2.9.99"));
+        assert_eq!(version("2.9.03"), version("2.9.3"));
+        assert_eq!(version(" v2.9.3 "), version("2.9.3"));
+    }
+
+    #[test]
+    fn orders_four_and_five_part_versions() {
+        assert!(version("2.5.6.61527.1") > version("2.5.6.61527"));
+        assert!(version("2.5.6.61527.1") < version("2.5.6.61527.2"));
+        assert!(version("2.5.6.61527.3") < version("2.5.6.61528"));
+        assert_ne!(version("2.5.6.61527.0"), version("2.5.6.61527"));
+    }
+
+    #[test]
+    fn keeps_malformed_versions_as_text() {
+        for text in ["", "beta", "2.9.x", "2..9", "-1.2", "1.2.3.4.5.6"] {
+            assert!(text.parse::<DcsVersion>().is_err(), "{:?}", text);
+            let lenient = version(text);
+            assert!(!lenient.is_known(), "{:?}", text);
+            assert_eq!(lenient.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn sorts_unknown_versions_first() {
+        let mut versions = [
+            version("2.9.3"),
+            version("beta"),
+            version("1.5"),
+            version(""),
+        ];
+        versions.sort();
+        let sorted: Vec<String> = versions.iter().map(ToString::to_string).collect();
+        assert_eq!(sorted, ["", "beta", "1.5", "2.9.3"]);
+
+        assert_eq!(version("beta"), version("beta"));
+        assert_ne!(version("beta"), version("alpha"));
+    }
+
+    #[test]
+    fn matches_requirements_to_their_precision() {
+        let v = version("2.9.3.51704");
+        assert!(req(">=2.9").matches(&v));
+        assert!(req("=2.9").matches(&v));
+        assert!(req("version == 2.9.3").matches(&v));
+        assert!(!req(">2.9").matches(&v));
+        assert!(req(">2.8").matches(&v));
+        assert!(req("<2.10").matches(&v));
+        assert!(req("<=2.9.3.51704").matches(&v));
+        assert!(!req("<2.9.3.51704").matches(&v));
+        assert!(req("!=2.8").matches(&v));
+        assert!(!req("!=2.9").matches(&v));
+        assert!(req("Version >= 2.9.3").matches(&v));
+    }
+
+    #[test]
+    fn never_matches_unknown_versions() {
+        for req in [req(">=1"), req("<99"), req("!=2.9")] {
+            assert!(!req.matches(&version("beta")));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_requirements() {
+        for text in ["2.9", "~2.9", ">=", ">= beta", "version"] {
+            assert!(text.parse::<VersionReq>().is_err(), "{:?}", text);
+        }
+    }
+}
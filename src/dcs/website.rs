@@ -0,0 +1,50 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use std::time::Duration;
+
+use super::changelog::parse_changelog;
+use super::session::{rate_limit, DcsSession};
+use super::{DcsError, Endpoints, Release, ServerSource, Servers};
+
+/**
+ * Scrapes server listings and version numbers from digitalcombatsimulator.com,
+ * logging in with the given DCS account when needed
+ */
+pub struct WebsiteSource {
+    session: DcsSession,
+}
+
+impl WebsiteSource {
+    pub fn new(endpoints: Endpoints, username: String, password: String) -> Self {
+        WebsiteSource {
+            session: DcsSession::new(endpoints, username, password),
+        }
+    }
+}
+
+#[async_trait]
+impl ServerSource for WebsiteSource {
+    async fn fetch_servers(&mut self) -> Result<Servers, DcsError> {
+        self.session.get_servers().await
+    }
+
+    async fn fetch_releases(&mut self) -> Result<Vec<Release>, DcsError> {
+        get_releases(self.session.client(), self.session.endpoints()).await
+    }
+}
+
+async fn get_releases(client: &Client, endpoints: &Endpoints) -> Result<Vec<Release>, DcsError> {
+    let res = client
+        .get(endpoints.changelog_url())
+        .timeout(Duration::from_secs(90))
+        .send()
+        .await?;
+
+    if let Some(err) = rate_limit(res.status(), res.headers()) {
+        return Err(err);
+    }
+
+    let page_url = res.url().to_string();
+    let page = res.text().await?;
+    parse_changelog(&page, &page_url)
+}
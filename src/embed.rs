@@ -0,0 +1,306 @@
+use serenity::builder::CreateEmbed;
+use serenity::model::Timestamp;
+
+use crate::dcs::Branch;
+
+// Discord's limits on a message's embeds
+pub const MAX_EMBEDS: usize = 10;
+pub const MAX_FIELDS: usize = 25;
+pub const EMBED_LIMIT: usize = 6000; // characters, across all of a message's embeds
+const TITLE_LIMIT: usize = 256;
+const DESCRIPTION_LIMIT: usize = 4096;
+const FIELD_NAME_LIMIT: usize = 256;
+const FIELD_VALUE_LIMIT: usize = 1024;
+
+const BAR_WIDTH: usize = 10;
+
+// Embed colours for each branch, and for versions that aren't the newest of
+// either. Also used for listings with a field per server
+const OPEN_BETA: u32 = 0xe67e22;
+const STABLE: u32 = 0x2ecc71;
+const OTHER: u32 = 0x95a5a6;
+pub const LISTING: u32 = 0x5865f2;
+
+/**
+ * A Discord embed as we build it, so it can be compared with the last one
+ * sent before it's turned into serenity's builder
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Embed {
+    pub title: String,
+    pub description: String,
+    pub colour: u32,
+    pub fields: Vec<(String, String)>,
+    pub footer: Option<String>,
+}
+
+impl Embed {
+    /**
+     * Roughly how much of a message's EMBED_LIMIT this uses
+     */
+    pub fn size(&self) -> usize {
+        let fields: usize = self
+            .fields
+            .iter()
+            .map(|(name, value)| name.chars().count() + value.chars().count())
+            .sum();
+        self.title.chars().count()
+            + self.description.chars().count()
+            + self
+                .footer
+                .as_ref()
+                .map_or(0, |footer| footer.chars().count())
+            + fields
+    }
+
+    /**
+     * Cuts this down to fit Discord's limits: each part to its own, then
+     * trailing fields and finally the end of the description until the whole
+     * is at most `limit` characters
+     */
+    pub fn clip(&mut self, limit: usize) {
+        self.title = truncate(&self.title, TITLE_LIMIT);
+        self.description = truncate(&self.description, DESCRIPTION_LIMIT);
+        self.fields.truncate(MAX_FIELDS);
+        for (name, value) in &mut self.fields {
+            *name = truncate(name, FIELD_NAME_LIMIT);
+            *value = truncate(value, FIELD_VALUE_LIMIT);
+        }
+
+        while self.size() > limit && !self.fields.is_empty() {
+            self.fields.pop();
+        }
+        if self.size() > limit {
+            let rest = self.size() - self.description.chars().count();
+            self.description = truncate(&self.description, limit.saturating_sub(rest));
+        }
+    }
+
+    /**
+     * Turns this into serenity's builder, stamped with when it was sent
+     */
+    pub fn build(&self, timestamp: i64) -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+        embed.title(truncate(&self.title, TITLE_LIMIT));
+        if !self.description.is_empty() {
+            embed.description(truncate(&self.description, DESCRIPTION_LIMIT));
+        }
+        embed.colour(self.colour);
+        for (name, value) in &self.fields {
+            embed.field(
+                truncate(name, FIELD_NAME_LIMIT),
+                truncate(value, FIELD_VALUE_LIMIT),
+                false,
+            );
+        }
+        if let Some(footer) = &self.footer {
+            embed.footer(|f| f.text(footer));
+        }
+        if let Ok(timestamp) = Timestamp::from_unix_timestamp(timestamp) {
+            embed.timestamp(timestamp);
+        }
+        embed
+    }
+}
+
+/**
+ * One message's worth of a subscription: text, embeds or both
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Page {
+    pub content: String,
+    pub embeds: Vec<Embed>,
+}
+
+impl Page {
+    pub fn text(content: String) -> Self {
+        Page {
+            content,
+            embeds: vec![],
+        }
+    }
+
+    pub fn build_embeds(&self, timestamp: i64) -> Vec<CreateEmbed> {
+        self.embeds
+            .iter()
+            .map(|embed| embed.build(timestamp))
+            .collect()
+    }
+}
+
+/**
+ * The colour for servers on a version, given the branch it's the newest
+ * release of (if any)
+ */
+pub fn branch_colour(branch: Option<Branch>) -> u32 {
+    match branch {
+        Some(Branch::OpenBeta) => OPEN_BETA,
+        Some(Branch::Stable) => STABLE,
+        None => OTHER,
+    }
+}
+
+/**
+ * The same as a coloured circle, for where embeds can't be coloured (the
+ * fields of a listing)
+ */
+pub fn branch_marker(branch: Option<Branch>) -> &'static str {
+    match branch {
+        Some(Branch::OpenBeta) => "🟠",
+        Some(Branch::Stable) => "🟢",
+        None => "⚪",
+    }
+}
+
+/**
+ * How full a server is, as a bar like ▰▰▰▱▱▱▱▱▱▱
+ */
+pub fn player_bar(players: u32, max: u32) -> String {
+    let filled = match max {
+        0 => 0,
+        max => ((players as f64 / max as f64) * BAR_WIDTH as f64).round() as usize,
+    }
+    .min(BAR_WIDTH);
+    format!("{}{}", "▰".repeat(filled), "▱".repeat(BAR_WIDTH - filled))
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((index, _)) => text[..index].to_string(),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn built(embed: &Embed) -> Value {
+        Value::from(serenity::json::hashmap_to_json_map(embed.build(0).0))
+    }
+
+    #[test]
+    fn counts_characters_towards_the_limit() {
+        let embed = Embed {
+            title: "★ Alpha".to_string(),
+            description: "ＪＴＦ".to_string(),
+            fields: vec![("é".to_string(), "🟠 1/2".to_string())],
+            footer: Some("v2.9".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(embed.size(), 7 + 3 + 1 + 5 + 4);
+    }
+
+    #[test]
+    fn truncates_to_discords_limits() {
+        let embed = Embed {
+            title: "é".repeat(TITLE_LIMIT + 1),
+            description: "é".repeat(DESCRIPTION_LIMIT + 1),
+            fields: vec![(
+                "é".repeat(FIELD_NAME_LIMIT + 1),
+                "é".repeat(FIELD_VALUE_LIMIT + 1),
+            )],
+            ..Default::default()
+        };
+        let json = built(&embed);
+        assert_eq!(json["title"], "é".repeat(TITLE_LIMIT));
+        assert_eq!(json["description"], "é".repeat(DESCRIPTION_LIMIT));
+        assert_eq!(json["fields"][0]["name"], "é".repeat(FIELD_NAME_LIMIT));
+        assert_eq!(json["fields"][0]["value"], "é".repeat(FIELD_VALUE_LIMIT));
+    }
+
+    #[test]
+    fn clips_to_the_whole_embed_limit() {
+        let field = (
+            "n".repeat(FIELD_NAME_LIMIT + 10),
+            "v".repeat(FIELD_VALUE_LIMIT),
+        );
+        let mut embed = Embed {
+            title: "é".repeat(TITLE_LIMIT + 10),
+            description: "d".repeat(100),
+            fields: vec![field; MAX_FIELDS + 2],
+            footer: Some("+1 more server".to_string()),
+            ..Default::default()
+        };
+        embed.clip(EMBED_LIMIT);
+        assert!(embed.size() <= EMBED_LIMIT);
+        assert_eq!(embed.title.chars().count(), TITLE_LIMIT);
+        assert_eq!(embed.description.len(), 100);
+        // Whole fields are dropped from the end first
+        let room = EMBED_LIMIT - TITLE_LIMIT - 100 - 14;
+        assert_eq!(
+            embed.fields.len(),
+            room / (FIELD_NAME_LIMIT + FIELD_VALUE_LIMIT)
+        );
+        assert_eq!(embed.fields[0].0.len(), FIELD_NAME_LIMIT);
+
+        // Then the description is cut short
+        let mut embed = Embed {
+            title: "Alpha".to_string(),
+            description: "é".repeat(DESCRIPTION_LIMIT),
+            ..Default::default()
+        };
+        embed.clip(1000);
+        assert_eq!(embed.size(), 1000);
+        assert_eq!(embed.description.chars().count(), 995);
+
+        // Anything already small enough is left alone
+        let small = Embed {
+            title: "Alpha".to_string(),
+            fields: vec![("Mission".to_string(), "Syria".to_string())],
+            ..Default::default()
+        };
+        let mut clipped = small.clone();
+        clipped.clip(EMBED_LIMIT);
+        assert_eq!(clipped, small);
+    }
+
+    #[test]
+    fn builds_fields_footer_and_timestamp() {
+        let embed = Embed {
+            title: "Alpha".to_string(),
+            colour: STABLE,
+            fields: vec![
+                ("Mission".to_string(), "Syria".to_string()),
+                ("Players".to_string(), "4/16".to_string()),
+            ],
+            footer: Some("🔒 Password".to_string()),
+            ..Default::default()
+        };
+        let json = built(&embed);
+        assert_eq!(json["color"], STABLE);
+        assert_eq!(json.get("description"), None);
+        assert_eq!(
+            json["fields"],
+            json!([
+                {"name": "Mission", "value": "Syria", "inline": false},
+                {"name": "Players", "value": "4/16", "inline": false},
+            ])
+        );
+        assert_eq!(json["footer"]["text"], "🔒 Password");
+        assert_eq!(json["timestamp"], "1970-01-01T00:00:00Z");
+
+        let bare = built(&Embed::default());
+        assert_eq!(bare.get("footer"), None);
+    }
+
+    #[test]
+    fn draws_player_bars() {
+        assert_eq!(player_bar(0, 16), "▱▱▱▱▱▱▱▱▱▱");
+        assert_eq!(player_bar(4, 16), "▰▰▰▱▱▱▱▱▱▱");
+        assert_eq!(player_bar(16, 16), "▰▰▰▰▰▰▰▰▰▰");
+        // More players than slots, or no slots at all, stay within the bar
+        assert_eq!(player_bar(20, 16), "▰▰▰▰▰▰▰▰▰▰");
+        assert_eq!(player_bar(3, 0), "▱▱▱▱▱▱▱▱▱▱");
+    }
+
+    #[test]
+    fn colours_and_marks_branches() {
+        assert_eq!(branch_colour(Some(Branch::OpenBeta)), OPEN_BETA);
+        assert_eq!(branch_colour(Some(Branch::Stable)), STABLE);
+        assert_eq!(branch_colour(None), OTHER);
+        assert_eq!(branch_marker(Some(Branch::OpenBeta)), "🟠");
+        assert_eq!(branch_marker(None), "⚪");
+    }
+}
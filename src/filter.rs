@@ -0,0 +1,989 @@
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+
+use crate::dcs::{Server, VersionReq};
+use crate::{fuzzy, normalize};
+
+// Limits on subscription regexes, so nobody can hand the bot one that takes
+// ages to compile or run against every server on every poll
+const REGEX_MAX_LENGTH: usize = 256;
+const REGEX_SIZE_LIMIT: usize = 64 * 1024;
+const REGEX_DFA_SIZE_LIMIT: usize = 256 * 1024;
+const REGEX_NEST_LIMIT: u32 = 16;
+
+// How deep brackets and NOTs can go in a filter, so parsing one can't
+// overflow the stack
+const FILTER_NEST_LIMIT: usize = 32;
+
+/**
+ * Server fields that can be searched for text, e.g. `mission:syria`
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextField {
+    Name,
+    Mission,
+    Ip,
+    Desc,
+}
+
+/**
+ * Server fields that hold counts, e.g. `players>=4`
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumberField {
+    Players,
+    Slots,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn compare(&self, left: u32, right: u32) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+/**
+ * A parsed subscription filter, e.g.
+ * `name:"growling sidewinder" OR (mission:syria AND players>=4)`. Plain
+ * words with no field match on the server name, so older filters like
+ * `australia` keep working
+ */
+#[derive(Clone, Debug)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Text(TextField, String), // lowercase
+    Number(NumberField, Comparison, u32),
+    Version(VersionReq),
+}
+
+/**
+ * Why a filter couldn't be parsed, worded for whoever typed it
+ */
+#[derive(Debug, PartialEq)]
+pub struct FilterError(pub String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word { text: String, quoted: bool },
+}
+
+const OPERATORS: [&str; 7] = [">=", "<=", "!=", ">", "<", "=", ":"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                // A word runs until whitespace or a bracket, but quoted parts
+                // (e.g. name:"big server") can hold anything
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    if c != '"' {
+                        word.push(c);
+                        continue;
+                    }
+
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => word.push(c),
+                            None => {
+                                return Err(FilterError("A quote (\") isn't closed".to_string()))
+                            }
+                        }
+                    }
+                }
+                tokens.push(Token::Word { text: word, quoted });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/**
+ * Splits e.g. `players>=4` into its field, operator and value, if it's a
+ * comparison on a field we know about
+ */
+fn split_comparison(word: &str) -> Option<(&str, &str, &str)> {
+    let (index, op) = OPERATORS
+        .iter()
+        .filter_map(|op| word.find(op).map(|index| (index, *op)))
+        // The earliest operator, and the longest one at that spot
+        .min_by_key(|(index, op)| (*index, std::cmp::Reverse(op.len())))?;
+
+    let field = &word[..index];
+    is_field(field).then(|| (field, op, &word[index + op.len()..]))
+}
+
+/**
+ * Text is searched case folded and without decorations, so "jtf" finds
+ * "★ [ＪＴＦ] ★"; addresses are left alone
+ */
+fn fold_value(field: TextField, value: &str) -> String {
+    match field {
+        TextField::Ip => value.to_string(),
+        _ => normalize::fold(value),
+    }
+}
+
+/**
+ * Whether a server's text contains the text searched for, or in fuzzy mode
+ * comes close enough to it. Either way it's the text as displayed that's
+ * searched, with entities decoded and the website's line breaks taken out
+ */
+fn text_matches(server_text: &str, text: &str, fuzzy: bool) -> bool {
+    let display = normalize::display_name(server_text);
+    normalize::fold(&display).contains(text)
+        || (fuzzy
+            && fuzzy::similarity(&fuzzy::words(text), &fuzzy::words(&display))
+                >= fuzzy::MATCH_THRESHOLD)
+}
+
+fn is_field(word: &str) -> bool {
+    matches!(
+        word.to_lowercase().as_str(),
+        "name" | "mission" | "version" | "ip" | "desc" | "players" | "slots"
+    )
+}
+
+fn is_operator(word: &str) -> bool {
+    OPERATORS.contains(&word) && word != ":"
+}
+
+/**
+ * The operator a word starts with, e.g. ">=" for ">=4"
+ */
+fn leading_operator(word: &str) -> Option<&'static str> {
+    OPERATORS.into_iter().find(|op| word.starts_with(op))
+}
+
+/**
+ * Builds the filter for one field comparison
+ */
+fn comparison(field: &str, op: &str, value: &str) -> Result<Filter, FilterError> {
+    let field = field.to_lowercase();
+    if value.is_empty() {
+        return Err(FilterError(format!("Nothing to compare {} with", field)));
+    }
+
+    let text_field = match field.as_str() {
+        "name" => Some(TextField::Name),
+        "mission" => Some(TextField::Mission),
+        "ip" => Some(TextField::Ip),
+        "desc" => Some(TextField::Desc),
+        _ => None,
+    };
+    if let Some(text_field) = text_field {
+        if op != ":" {
+            return Err(FilterError(format!(
+                "{} can only be searched with ':', e.g. {}:australia",
+                field, field
+            )));
+        }
+        return Ok(Filter::Text(text_field, fold_value(text_field, value)));
+    }
+
+    if field == "version" {
+        // version:2.9 means any 2.9 release
+        let op = if op == ":" { "=" } else { op };
+        return format!("{}{}", op, value)
+            .parse::<VersionReq>()
+            .map(Filter::Version)
+            .map_err(|_| {
+                FilterError(format!(
+                    "'{}' isn't a version number, e.g. version>=2.9",
+                    value
+                ))
+            });
+    }
+
+    let number_field = match field.as_str() {
+        "players" => NumberField::Players,
+        _ => NumberField::Slots,
+    };
+    let comparison = match op {
+        ">=" => Comparison::GreaterOrEqual,
+        "<=" => Comparison::LessOrEqual,
+        "!=" => Comparison::NotEqual,
+        ">" => Comparison::Greater,
+        "<" => Comparison::Less,
+        _ => Comparison::Equal,
+    };
+    let number = value
+        .parse::<u32>()
+        .map_err(|_| FilterError(format!("'{}' isn't a number, e.g. {}>=4", value, field)))?;
+    Ok(Filter::Number(number_field, comparison, number))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word { text, quoted: false }) if text == keyword)
+    }
+
+    fn or(&mut self) -> Result<Filter, FilterError> {
+        let mut filter = self.and()?;
+        while self.peek_keyword("OR") {
+            self.position += 1;
+            if matches!(self.peek(), None | Some(Token::Close)) {
+                return Err(FilterError("Expected something after OR".to_string()));
+            }
+            let right = self.and()?;
+            filter = Filter::Or(Box::new(filter), Box::new(right));
+        }
+        Ok(filter)
+    }
+
+    /**
+     * Terms next to each other have to all match, with or without AND
+     */
+    fn and(&mut self) -> Result<Filter, FilterError> {
+        let mut filter = self.not()?;
+        loop {
+            if self.peek_keyword("AND") {
+                self.position += 1;
+                if matches!(self.peek(), None | Some(Token::Close)) {
+                    return Err(FilterError("Expected something after AND".to_string()));
+                }
+                let right = self.not()?;
+                filter = Filter::And(Box::new(filter), Box::new(right));
+                continue;
+            }
+
+            match self.peek() {
+                None | Some(Token::Close) => break,
+                _ if self.peek_keyword("OR") => break,
+                _ => {
+                    let right = self.not()?;
+                    filter = Filter::And(Box::new(filter), Box::new(right));
+                }
+            }
+        }
+        Ok(filter)
+    }
+
+    /**
+     * Parses something inside brackets or after a NOT, one level deeper
+     */
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Filter, FilterError>,
+    ) -> Result<Filter, FilterError> {
+        if self.depth >= FILTER_NEST_LIMIT {
+            return Err(FilterError(format!(
+                "The filter is nested too deeply (at most {} brackets or NOTs)",
+                FILTER_NEST_LIMIT
+            )));
+        }
+        self.depth += 1;
+        let filter = parse(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn not(&mut self) -> Result<Filter, FilterError> {
+        if self.peek_keyword("NOT") {
+            self.position += 1;
+            if self.peek().is_none() {
+                return Err(FilterError("Expected something after NOT".to_string()));
+            }
+            return Ok(Filter::Not(Box::new(self.nested(Self::not)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Filter, FilterError> {
+        match self.peek() {
+            Some(Token::Open) => {
+                self.position += 1;
+                if self.peek() == Some(&Token::Close) {
+                    return Err(FilterError("Empty brackets".to_string()));
+                }
+                let filter = self.nested(Self::or)?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(FilterError("A bracket isn't closed".to_string()));
+                }
+                self.position += 1;
+                Ok(filter)
+            }
+            Some(Token::Close) => Err(FilterError("Unexpected ')'".to_string())),
+            None => Err(FilterError("The filter is empty".to_string())),
+            Some(Token::Word { .. }) => self.term(),
+        }
+    }
+
+    fn take_word(&mut self) -> Option<(String, bool)> {
+        match self.tokens.get(self.position) {
+            Some(Token::Word { text, quoted }) => {
+                self.position += 1;
+                Some((text.clone(), *quoted))
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * The value after a spaced out operator, if there is one
+     */
+    fn take_value(&mut self) -> String {
+        self.take_word().map(|(value, _)| value).unwrap_or_default()
+    }
+
+    /**
+     * A field comparison, or a run of plain words to find in the name
+     */
+    fn term(&mut self) -> Result<Filter, FilterError> {
+        let (word, quoted) = self.take_word().expect("term without a word");
+
+        if !quoted {
+            // "players >= 4", "players >=4", "version >= 2.9"
+            if is_field(&word) {
+                if let Some(Token::Word {
+                    text: next,
+                    quoted: false,
+                }) = self.peek()
+                {
+                    if let Some(op) = leading_operator(next) {
+                        let value = next[op.len()..].to_string();
+                        self.position += 1;
+                        if value.is_empty() {
+                            return comparison(&word, op, &self.take_value());
+                        }
+                        return comparison(&word, op, &value);
+                    }
+                }
+            }
+
+            // ">= 2.9" on its own compares versions, as older filters did
+            let unprefixed = word.trim_start_matches(['<', '>', '=', '!']);
+            if is_operator(&word)
+                || (unprefixed != word && unprefixed.starts_with(|c: char| c.is_ascii_digit()))
+            {
+                let mut text = word.clone();
+                if is_operator(&word) {
+                    text += &self.take_value();
+                }
+                return text
+                    .parse::<VersionReq>()
+                    .map(Filter::Version)
+                    .map_err(|_| {
+                        FilterError(format!(
+                            "'{}' isn't a version comparison, e.g. version>=2.9",
+                            text
+                        ))
+                    });
+            }
+        }
+
+        // name:australia, players>=4, or name:"two words" (the quotes are
+        // only around the value, so the word itself isn't marked quoted)
+        if let Some((field, op, value)) = split_comparison(&word) {
+            // "players>= 4"
+            if value.is_empty() && !quoted {
+                return comparison(field, op, &self.take_value());
+            }
+            return comparison(field, op, value);
+        }
+
+        // Plain words next to each other make one phrase
+        let mut phrase = vec![word];
+        while let Some(Token::Word { text, quoted }) = self.peek() {
+            let keyword = !quoted && (text == "AND" || text == "OR" || text == "NOT");
+            if keyword || (!quoted && split_comparison(text).is_some()) {
+                break;
+            }
+            // ...up to a spaced out comparison like "players >= 4"
+            let next = self.tokens.get(self.position + 1);
+            let before_operator = matches!(next, Some(Token::Word { text, quoted: false })
+                if leading_operator(text).is_some());
+            if !quoted && (is_operator(text) || (is_field(text) && before_operator)) {
+                break;
+            }
+            phrase.push(text.clone());
+            self.position += 1;
+        }
+        Ok(Filter::Text(
+            TextField::Name,
+            normalize::fold(&phrase.join(" ")),
+        ))
+    }
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Result<Filter, FilterError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            depth: 0,
+        };
+        let filter = parser.or()?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some(Token::Close) => Err(FilterError("Unexpected ')'".to_string())),
+            Some(_) => Err(FilterError("Couldn't understand the filter".to_string())),
+        }
+    }
+
+    /**
+     * Matches the whole text against server names, for filters saved before
+     * the query language
+     */
+    pub fn phrase(text: &str) -> Filter {
+        Filter::Text(TextField::Name, normalize::fold(text))
+    }
+
+    /**
+     * The text searched for in names (outside of any NOT), which near misses
+     * can be suggested for when nothing matches
+     */
+    pub fn name_terms(&self) -> Vec<&str> {
+        match self {
+            Filter::And(left, right) | Filter::Or(left, right) => {
+                let mut terms = left.name_terms();
+                terms.extend(right.name_terms());
+                terms
+            }
+            Filter::Text(TextField::Name, text) => vec![text.as_str()],
+            _ => vec![],
+        }
+    }
+
+    /**
+     * Whether a server matches; with `fuzzy`, text only has to come close
+     * to the server's (see fuzzy::similarity) rather than be found in it
+     */
+    pub fn matches(&self, server: &Server, fuzzy: bool) -> bool {
+        match self {
+            Filter::And(left, right) => left.matches(server, fuzzy) && right.matches(server, fuzzy),
+            Filter::Or(left, right) => left.matches(server, fuzzy) || right.matches(server, fuzzy),
+            Filter::Not(filter) => !filter.matches(server, fuzzy),
+            Filter::Text(field, text) => match field {
+                // A server that has renamed itself still matches on its old
+                // names, so subscriptions follow the server rather than its name
+                TextField::Name => std::iter::once(&server.NAME)
+                    .chain(&server.former_names)
+                    .any(|name| text_matches(name, text, fuzzy)),
+                TextField::Mission => text_matches(&server.MISSION_NAME, text, fuzzy),
+                TextField::Desc => text_matches(&server.DESCRIPTION, text, fuzzy),
+                TextField::Ip => {
+                    format!("{}:{}", server.IP_ADDRESS, server.PORT).contains(text.as_str())
+                }
+            },
+            Filter::Number(field, comparison, number) => {
+                // The website counts the server itself as a player
+                let players = server.PLAYERS.saturating_sub(1);
+                let value = match field {
+                    NumberField::Players => players,
+                    NumberField::Slots => server.PLAYERS_MAX.saturating_sub(players),
+                };
+                comparison.compare(value, *number)
+            }
+            Filter::Version(req) => req.matches(&server.DCS_VERSION),
+        }
+    }
+}
+
+/**
+ * Splits a filter like `/^\[JTF\]/i players>0` into its regex and the rest.
+ * A filter that doesn't start with a / has no regex
+ */
+pub fn split_regex(text: &str) -> Result<(Option<&str>, &str), FilterError> {
+    let text = text.trim();
+    if !text.starts_with('/') {
+        return Ok((None, text));
+    }
+
+    // Find the closing /, skipping escaped characters
+    let mut escaped = false;
+    let mut end = None;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '/' => {
+                end = Some(index);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let end = end.ok_or_else(|| FilterError("The regex needs a closing /".to_string()))?;
+
+    // Flags run up to the first space
+    let flags_end = text[end..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |index| end + index);
+    Ok((Some(&text[..flags_end]), text[flags_end..].trim()))
+}
+
+/**
+ * Compiles a `/pattern/flags` regex, within limits. Flags are i (ignore
+ * case), m (multi-line), s (. matches newlines) and x (ignore whitespace)
+ */
+pub fn compile_regex(spec: &str) -> Result<Regex, FilterError> {
+    let end = spec.rfind('/').filter(|end| *end > 0);
+    let (pattern, flags) = match (spec.strip_prefix('/'), end) {
+        (Some(_), Some(end)) => (&spec[1..end], &spec[end + 1..]),
+        _ => return Err(FilterError("A regex looks like /pattern/flags".to_string())),
+    };
+
+    if pattern.is_empty() {
+        return Err(FilterError("The regex is empty".to_string()));
+    }
+    if pattern.len() > REGEX_MAX_LENGTH {
+        return Err(FilterError(format!(
+            "The regex is too long (at most {} characters)",
+            REGEX_MAX_LENGTH
+        )));
+    }
+
+    let mut builder = RegexBuilder::new(pattern);
+    builder
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .nest_limit(REGEX_NEST_LIMIT);
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            _ => {
+                return Err(FilterError(format!(
+                    "Unknown regex flag '{}' (use i, m, s or x)",
+                    flag
+                )))
+            }
+        };
+    }
+
+    builder.build().map_err(|err| match err {
+        regex::Error::CompiledTooBig(_) => FilterError("The regex is too complicated".to_string()),
+        // Syntax errors draw a diagram over several lines; the last one
+        // says what's wrong
+        err => {
+            let err = err.to_string();
+            let reason = err.lines().last().unwrap_or_default();
+            FilterError(format!(
+                "Invalid regex ({})",
+                reason.trim_start_matches("error: ")
+            ))
+        }
+    })
+}
+
+/**
+ * Everything a subscription matches servers on: a regex on the name and a
+ * query, either of which can be left out. Servers have to match both
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SubFilter {
+    regex: Option<Regex>,
+    query: Option<Filter>,
+}
+
+impl SubFilter {
+    pub fn new(regex: Option<&str>, query: &str) -> Result<SubFilter, FilterError> {
+        let regex = regex.map(compile_regex).transpose()?;
+        let query = match query.trim() {
+            "" if regex.is_some() => None,
+            query => Some(Filter::parse(query)?),
+        };
+        Ok(SubFilter { regex, query })
+    }
+
+    /**
+     * Parses a filter as typed after `!dcsbot subscribe`, returning the regex
+     * (if any) and the rest of the filter as they should be saved
+     */
+    pub fn parse(text: &str) -> Result<(SubFilter, Option<String>, String), FilterError> {
+        let (regex, query) = split_regex(text)?;
+        let filter = SubFilter::new(regex, query)?;
+        Ok((filter, regex.map(str::to_string), query.to_string()))
+    }
+
+    /**
+     * See Filter::phrase
+     */
+    pub fn phrase(text: &str) -> SubFilter {
+        SubFilter {
+            regex: None,
+            query: Some(Filter::phrase(text)),
+        }
+    }
+
+    /**
+     * See Filter::name_terms
+     */
+    pub fn name_terms(&self) -> Vec<&str> {
+        self.query
+            .as_ref()
+            .map(Filter::name_terms)
+            .unwrap_or_default()
+    }
+
+    pub fn matches(&self, server: &Server, fuzzy: bool) -> bool {
+        if let Some(regex) = &self.regex {
+            let mut names = std::iter::once(&server.NAME).chain(&server.former_names);
+            if !names.any(|name| regex.is_match(&normalize::display_name(name))) {
+                return false;
+            }
+        }
+        self.query
+            .as_ref()
+            .is_none_or(|query| query.matches(server, fuzzy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    fn server(fields: Value) -> Server {
+        let mut entry = json!({
+            "NAME": "=GS= Growling Sidewinder",
+            "IP_ADDRESS": "192.0.2.1",
+            "PORT": "10308",
+            "MISSION_NAME": "Syria Liberation",
+            "DESCRIPTION": "PvE, all welcome",
+            "PLAYERS": "5",
+            "PLAYERS_MAX": "32",
+            "DCS_VERSION": "2.9.3.51704",
+        });
+        for (key, value) in fields.as_object().unwrap() {
+            entry[key] = value.clone();
+        }
+        Server::deserialize(&entry).unwrap()
+    }
+
+    /**
+     * The filter's structure, with its grouping spelled out
+     */
+    fn show(filter: &Filter) -> String {
+        match filter {
+            Filter::And(left, right) => format!("({} AND {})", show(left), show(right)),
+            Filter::Or(left, right) => format!("({} OR {})", show(left), show(right)),
+            Filter::Not(filter) => format!("NOT {}", show(filter)),
+            Filter::Text(field, text) => format!("{:?}:{:?}", field, text),
+            Filter::Number(field, comparison, number) => {
+                format!("{:?} {:?} {}", field, comparison, number)
+            }
+            Filter::Version(_) => "Version".to_string(),
+        }
+    }
+
+    fn parsed(text: &str) -> String {
+        match Filter::parse(text) {
+            Ok(filter) => show(&filter),
+            Err(err) => panic!("{:?} didn't parse: {}", text, err),
+        }
+    }
+
+    fn error(text: &str) -> String {
+        match Filter::parse(text) {
+            Ok(filter) => panic!("{:?} parsed as {}", text, show(&filter)),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn matches(filter: &str, server: &Server) -> bool {
+        Filter::parse(filter).unwrap().matches(server, false)
+    }
+
+    fn word(text: &str, quoted: bool) -> Token {
+        Token::Word {
+            text: text.to_string(),
+            quoted,
+        }
+    }
+
+    #[test]
+    fn tokenizes() {
+        assert_eq!(
+            tokenize(r#"(name:"big  (server)" OR x)y "#).unwrap(),
+            [
+                Token::Open,
+                word("name:big  (server)", true),
+                word("OR", false),
+                word("x", false),
+                Token::Close,
+                word("y", false),
+            ]
+        );
+        assert_eq!(tokenize(" \t ").unwrap(), []);
+        assert_eq!(tokenize(r#""""#).unwrap(), [word("", true)]);
+    }
+
+    #[test]
+    fn joins_plain_words_into_phrases() {
+        assert_eq!(parsed("australia"), r#"Name:"australia""#);
+        assert_eq!(
+            parsed("Growling  Sidewinder"),
+            r#"Name:"growling sidewinder""#
+        );
+        assert_eq!(parsed(r#""OR" more"#), r#"Name:"or more""#);
+        assert_eq!(parsed(r#"name:"Big Server""#), r#"Name:"big server""#);
+        assert_eq!(parsed("MISSION:Syria"), r#"Mission:"syria""#);
+        assert_eq!(parsed("mission: syria"), r#"Mission:"syria""#);
+        assert_eq!(parsed("mission : syria"), r#"Mission:"syria""#);
+        assert_eq!(parsed("ip:192.0.2.1:10308"), r#"Ip:"192.0.2.1:10308""#);
+    }
+
+    #[test]
+    fn binds_not_then_and_then_or() {
+        assert_eq!(
+            parsed("a OR b AND c"),
+            r#"(Name:"a" OR (Name:"b" AND Name:"c"))"#
+        );
+        assert_eq!(
+            parsed("a AND b OR c"),
+            r#"((Name:"a" AND Name:"b") OR Name:"c")"#
+        );
+        assert_eq!(parsed("NOT a AND b"), r#"(NOT Name:"a" AND Name:"b")"#);
+        assert_eq!(
+            parsed("(a OR b) mission:c"),
+            r#"((Name:"a" OR Name:"b") AND Mission:"c")"#
+        );
+        assert_eq!(parsed("NOT (a OR b)"), r#"NOT (Name:"a" OR Name:"b")"#);
+        assert_eq!(parsed("NOT NOT a"), r#"NOT NOT Name:"a""#);
+        // Lower case keywords are just words
+        assert_eq!(parsed("rock and roll"), r#"Name:"rock and roll""#);
+    }
+
+    #[test]
+    fn reads_spaced_comparisons() {
+        for text in ["players>=4", "players >= 4", "players>= 4", "Players >=4"] {
+            assert_eq!(parsed(text), "Players GreaterOrEqual 4", "{:?}", text);
+        }
+        assert_eq!(parsed("slots != 0"), "Slots NotEqual 0");
+        assert_eq!(parsed("players = 2"), "Players Equal 2");
+        assert_eq!(
+            parsed("big server players > 4"),
+            r#"(Name:"big server" AND Players Greater 4)"#
+        );
+        assert_eq!(
+            parsed("training mission:syria"),
+            r#"(Name:"training" AND Mission:"syria")"#
+        );
+        for text in [
+            "version >= 2.9",
+            "version>=2.9",
+            "version:2.9",
+            ">= 2.9",
+            ">=2.9",
+        ] {
+            assert_eq!(parsed(text), "Version", "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn explains_bad_filters() {
+        let cases = [
+            ("", "The filter is empty"),
+            ("(a", "A bracket isn't closed"),
+            ("a)", "Unexpected ')'"),
+            (")", "Unexpected ')'"),
+            ("()", "Empty brackets"),
+            ("a OR", "Expected something after OR"),
+            ("a OR )", "Expected something after OR"),
+            ("a AND", "Expected something after AND"),
+            ("NOT", "Expected something after NOT"),
+            (r#"name:"abc"#, "A quote (\") isn't closed"),
+            ("name:", "Nothing to compare name with"),
+            ("players >=", "Nothing to compare players with"),
+            ("players>=lots", "'lots' isn't a number, e.g. players>=4"),
+            ("players>-1", "'-1' isn't a number, e.g. players>=4"),
+            (
+                "mission>=3",
+                "mission can only be searched with ':', e.g. mission:australia",
+            ),
+            (
+                "version>=beta",
+                "'beta' isn't a version number, e.g. version>=2.9",
+            ),
+            (
+                ">= beta",
+                "'>=beta' isn't a version comparison, e.g. version>=2.9",
+            ),
+        ];
+        for (text, message) in cases {
+            assert_eq!(error(text), message, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(parsed(&nested(FILTER_NEST_LIMIT)), r#"Name:"a""#);
+        assert!(error(&nested(FILTER_NEST_LIMIT + 1)).contains("nested too deeply"));
+        assert!(error(&"(".repeat(100_000)).contains("nested too deeply"));
+        assert!(error(&format!("{}a", "NOT ".repeat(100_000))).contains("nested too deeply"));
+    }
+
+    #[test]
+    fn matches_servers() {
+        let server = server(json!({}));
+        assert!(matches("growling", &server));
+        assert!(matches("GROWLING sidewinder", &server));
+        assert!(!matches("sidewinder growling", &server));
+        assert!(matches("mission:syria desc:pve", &server));
+        assert!(matches("mission:caucasus OR name:growling", &server));
+        assert!(!matches("NOT name:growling", &server));
+        assert!(matches("ip:192.0.2.1:10308", &server));
+        assert!(!matches("ip:192.0.2.10", &server));
+
+        // The website counts the server as a player
+        assert!(matches("players=4", &server));
+        assert!(matches("slots>=28 slots<=28", &server));
+
+        assert!(matches("version>=2.9 version<2.10", &server));
+        assert!(!matches("version:2.8", &server));
+    }
+
+    #[test]
+    fn matches_former_names() {
+        let mut server = server(json!({"NAME": "New Name"}));
+        server.former_names = vec!["=GS= Growling Sidewinder".to_string()];
+        assert!(matches("growling", &server));
+        assert!(!matches("NOT growling", &server));
+    }
+
+    #[test]
+    fn matches_names_as_displayed() {
+        let server = server(json!({"NAME": "Tom &amp; Jerry&#39;s ★ [ＪＴＦ]"}));
+        assert!(matches("tom & jerry's", &server));
+        assert!(matches("[jtf]", &server));
+    }
+
+    #[test]
+    fn matches_fuzzily_on_names_as_displayed() {
+        let filter = Filter::parse("tom jery").unwrap();
+        let entities = server(json!({"NAME": "Tom &amp; Jerry"}));
+        assert!(!filter.matches(&entities, false));
+        assert!(filter.matches(&entities, true));
+
+        // Split by the website after 20 characters
+        let filter = Filter::parse("supercalifragilisticexpialidocous").unwrap();
+        let split = server(json!({"NAME": "Supercalifragilistic expialidocious PvE"}));
+        assert!(!filter.matches(&split, false));
+        assert!(filter.matches(&split, true));
+    }
+
+    #[test]
+    fn lists_name_terms_outside_not() {
+        let filter = Filter::parse("alpha OR (bravo AND NOT charlie) mission:delta").unwrap();
+        assert_eq!(filter.name_terms(), ["alpha", "bravo"]);
+    }
+
+    #[test]
+    fn splits_off_regexes() {
+        assert_eq!(
+            split_regex(r"/^\[JTF\]/i players>0").unwrap(),
+            (Some(r"/^\[JTF\]/i"), "players>0")
+        );
+        assert_eq!(split_regex(r"  /a\/b/  ").unwrap(), (Some(r"/a\/b/"), ""));
+        assert_eq!(split_regex("name:a/b").unwrap(), (None, "name:a/b"));
+        assert_eq!(
+            split_regex("/unclosed").unwrap_err().to_string(),
+            "The regex needs a closing /"
+        );
+    }
+
+    #[test]
+    fn compiles_regexes_within_limits() {
+        assert!(compile_regex("/^jtf/i").unwrap().is_match("JTF Training"));
+        assert!(compile_regex("/^a.b$/s").unwrap().is_match("a\nb"));
+
+        let error = |spec: &str| compile_regex(spec).unwrap_err().to_string();
+        assert_eq!(error("//"), "The regex is empty");
+        assert_eq!(error("/a/g"), "Unknown regex flag 'g' (use i, m, s or x)");
+        assert_eq!(error("no slashes"), "A regex looks like /pattern/flags");
+        assert!(error(&format!("/{}/", "a".repeat(REGEX_MAX_LENGTH + 1))).contains("too long"));
+        assert!(error("/(unclosed/").starts_with("Invalid regex"));
+        assert_eq!(error("/a{1000}{1000}/"), "The regex is too complicated");
+        assert!(
+            error(&format!("/{}a{}/", "(".repeat(20), ")".repeat(20))).starts_with("Invalid regex")
+        );
+    }
+
+    #[test]
+    fn combines_regex_and_query() {
+        let (filter, regex, query) = SubFilter::parse(r"/^=GS=/ players>=4").unwrap();
+        assert_eq!(regex.as_deref(), Some("/^=GS=/"));
+        assert_eq!(query, "players>=4");
+        assert!(filter.matches(&server(json!({})), false));
+        assert!(!filter.matches(&server(json!({"PLAYERS": "2"})), false));
+        assert!(!filter.matches(&server(json!({"NAME": "Other =GS="})), false));
+
+        // Either can be left out, but not both
+        let (regex_only, _, _) = SubFilter::parse("/Sidewinder$/").unwrap();
+        assert!(regex_only.matches(&server(json!({})), false));
+        assert_eq!(
+            SubFilter::parse("  ").unwrap_err().to_string(),
+            "The filter is empty"
+        );
+    }
+
+    #[test]
+    fn keeps_phrases_whole() {
+        let phrase = SubFilter::phrase("red OR blue");
+        assert!(phrase.matches(&server(json!({"NAME": "Red or Blue PvP"})), false));
+        assert!(!phrase.matches(&server(json!({"NAME": "Red Flag"})), false));
+    }
+}
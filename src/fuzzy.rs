@@ -0,0 +1,194 @@
+use strsim::normalized_damerau_levenshtein;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::normalize;
+
+// How close text has to be to count as a match in fuzzy mode, and to be
+// offered as a suggestion when nothing matches, from 0 to 1
+pub const MATCH_THRESHOLD: f64 = 0.8;
+pub const SUGGESTION_THRESHOLD: f64 = 0.5;
+
+/**
+ * Splits text into words for fuzzy matching, with case and accents folded
+ * away and punctuation dropped, so "=GS= Growling-Sidewinder" and
+ * "gs growling sidewinder" give the same words
+ */
+pub fn words(text: &str) -> Vec<String> {
+    let bare: String = normalize::fold(text)
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect();
+    bare.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/**
+ * How well the query's words are found among the text's, from 0 to 1: the
+ * average, over the query words, of how close the nearest word in the text
+ * is. Word order doesn't matter, and extra words in the text don't count
+ * against it
+ */
+pub fn similarity(query: &[String], text: &[String]) -> f64 {
+    if query.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = query
+        .iter()
+        .map(|query| {
+            text.iter()
+                .map(|word| word_similarity(query, word))
+                .fold(0.0, f64::max)
+        })
+        .sum();
+    total / query.len() as f64
+}
+
+/**
+ * Up to `count` of the names closest to the query, best first, leaving out
+ * any too far off to be worth suggesting
+ */
+pub fn suggestions(
+    query: &str,
+    names: impl IntoIterator<Item = String>,
+    count: usize,
+) -> Vec<String> {
+    let query = words(query);
+    if query.is_empty() {
+        return vec![];
+    }
+
+    let mut scored: Vec<(f64, String)> = names
+        .into_iter()
+        .map(|name| (similarity(&query, &words(&name)), name))
+        .filter(|(score, _)| *score >= SUGGESTION_THRESHOLD)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut names: Vec<String> = vec![];
+    for (_, name) in scored {
+        if names.len() == count {
+            break;
+        }
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/**
+ * Two words' similarity by edit distance, with typos and transpositions
+ * costing one edit. A word starting with a query of three or more letters
+ * matches fully, so "sidew" finds "sidewinder"
+ */
+fn word_similarity(query: &str, word: &str) -> f64 {
+    if query.chars().count() >= 3 && word.starts_with(query) {
+        return 1.0;
+    }
+    normalized_damerau_levenshtein(query, word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(query: &str, text: &str) -> f64 {
+        similarity(&words(query), &words(text))
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn splits_folded_words() {
+        assert_eq!(
+            words("=GS= Growling-Sidewinder"),
+            words("gs growling sidewinder")
+        );
+        assert_eq!(words("Équipe Française"), ["equipe", "francaise"]);
+        assert_eq!(words("★ ★"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn scores_exact_and_reordered_words_fully() {
+        assert_eq!(
+            score("growling sidewinder", "=GS= Growling Sidewinder"),
+            1.0
+        );
+        assert_eq!(
+            score("sidewinder growling", "=GS= Growling Sidewinder"),
+            1.0
+        );
+        assert_eq!(score("sidew", "=GS= Growling Sidewinder"), 1.0);
+        assert_eq!(score("", "=GS= Growling Sidewinder"), 0.0);
+    }
+
+    #[test]
+    fn matches_typos() {
+        for query in [
+            "growling sidewindr",
+            "growlnig sidewinder",
+            "grwoling sidewinder",
+            "GROWLING SIDEWINDER!",
+        ] {
+            let score = score(query, "=GS= Growling Sidewinder");
+            assert!(score >= MATCH_THRESHOLD, "{:?} scored {}", query, score);
+        }
+    }
+
+    #[test]
+    fn suggests_near_misses_without_matching_them() {
+        let score = score("growling rattlesnake", "=GS= Growling Sidewinder");
+        assert!(
+            (SUGGESTION_THRESHOLD..MATCH_THRESHOLD).contains(&score),
+            "scored {}",
+            score
+        );
+    }
+
+    #[test]
+    fn ignores_unrelated_names() {
+        for text in ["Red Flag PvP", "Syria Training", ""] {
+            let score = score("growling sidewinder", text);
+            assert!(score < SUGGESTION_THRESHOLD, "{:?} scored {}", text, score);
+        }
+        // Short words only match from the start of a word when they're exact
+        assert!(score("gs", "Growling Sidewinder") < SUGGESTION_THRESHOLD);
+    }
+
+    #[test]
+    fn suggests_the_closest_names() {
+        let servers = names(&[
+            "Red Flag PvP",
+            "=GS= Growling Sidewinder",
+            "Growling Sidewinder Training",
+            "=GS= Growling Sidewinder",
+            "Rattlesnake Range",
+            "Sidewinder Academy",
+        ]);
+        assert_eq!(
+            suggestions("growling sidewindr", servers.clone(), 3),
+            [
+                "=GS= Growling Sidewinder",
+                "Growling Sidewinder Training",
+                "Sidewinder Academy"
+            ]
+        );
+        assert_eq!(
+            suggestions("growling sidewindr", servers.clone(), 1),
+            ["=GS= Growling Sidewinder"]
+        );
+    }
+
+    #[test]
+    fn suggests_nothing_when_nothing_is_close() {
+        let servers = names(&["Red Flag PvP", "Syria Training"]);
+        assert!(suggestions("growling sidewinder", servers.clone(), 3).is_empty());
+        assert!(suggestions("", servers.clone(), 3).is_empty());
+        assert!(suggestions("★", servers, 3).is_empty());
+    }
+}
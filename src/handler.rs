@@ -5,18 +5,46 @@ use serenity::model::id::GuildId;
 use serenity::model::permissions::Permissions;
 use serenity::model::user::User;
 use serenity::prelude::*;
+use std::time::Duration;
 use tokio::sync::mpsc::UnboundedSender;
 
+use crate::config::{OfflineAction, SortKey, SortOrder, Style, SubMode};
+use crate::filter::SubFilter;
+
 #[derive(Debug)]
 pub enum HandlerMessage {
-    SubscribeChannel(u64, String), // channel_id, filter
-    UnsubscribeChannel(u64),
+    SubscribeChannel(u64, Option<String>, Option<String>, String, SubFilter), // channel_id, name, regex, filter, both compiled
+    UnsubscribeChannel(u64, Option<String>),                                  // channel_id, name
+    ListSubscriptions(u64, u64),         // channel_id, guild_id
+    SubscribeReleases(u64, Option<u64>), // channel_id, role_id to ping
+    UnsubscribeReleases(u64),
+    History(u64, String, u32),           // channel_id, filter, days
+    Chart(u64, String, Duration),        // channel_id, filter, how far back
+    SetChart(u64, Option<String>, bool), // channel_id, subscription name, on/off
+    SetFuzzy(u64, Option<String>, bool),
+    SetSort(u64, Option<String>, SortKey, SortOrder),
+    SetLimit(u64, Option<String>, usize),
+    SetMode(u64, Option<String>, SubMode),
+    SetStyle(u64, Option<String>, Style),
+    SetOffline(u64, Option<String>, OfflineAction),
+    Heatmap(u64, u64, String, u32), // channel_id, guild_id, filter, days
+    SetTimezone(u64, u64, String),  // channel_id, guild_id, timezone name
 }
 
 pub struct Handler {
     pub handler_tx: UnboundedSender<HandlerMessage>,
 }
 
+/**
+ * Subscription names are one short word, so commands can refer to them
+ */
+fn is_valid_name(name: &str) -> bool {
+    name.chars().count() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
 fn is_authorized_user(
     channel: GuildChannel,
     cache: &std::sync::Arc<serenity::cache::Cache>,
@@ -33,7 +61,7 @@ fn is_authorized_user(
 impl EventHandler for Handler {
     async fn message(&self, context: Context, msg: Message) {
         let mut components = msg.content.split(" ");
-        if components.next().as_deref().unwrap_or_default() != "!dcsbot" {
+        if components.next().unwrap_or_default() != "!dcsbot" {
             return;
         }
 
@@ -46,6 +74,7 @@ impl EventHandler for Handler {
         };
 
         let channel_id = channel.id.0;
+        let guild_id = channel.guild_id.0;
 
         if !is_authorized_user(channel, &context.cache, &msg.author) {
             println!("User was not an admin");
@@ -56,19 +85,53 @@ impl EventHandler for Handler {
             return;
         }
 
-        match components.next().as_deref() {
+        match components.next() {
             Some("subscribe") => {
                 // Split.as_str() would be nice here
                 let mut filter = vec![];
-                while let Some(word) = components.next() {
+                for word in components {
                     filter.push(word);
                 }
-                if filter.len() > 0 {
+
+                // `subscribe as <name> <filter>` names the subscription
+                let mut name = None;
+                if filter.len() > 2 && filter[0] == "as" {
+                    if !is_valid_name(filter[1]) {
+                        let _ = msg
+                            .channel_id
+                            .say(
+                                &context.http,
+                                "Subscription names are one word of up to 32 letters, digits, - or _",
+                            )
+                            .await;
+                        return;
+                    }
+                    name = Some(filter[1].to_string());
+                    filter.drain(..2);
+                }
+
+                if !filter.is_empty() {
                     let filter_text = filter.join(" ");
-                    let _ = self.handler_tx.send(HandlerMessage::SubscribeChannel(
-                        channel_id,
-                        filter_text.to_string(),
-                    ));
+                    match SubFilter::parse(&filter_text) {
+                        Ok((matcher, regex, filter)) => {
+                            let _ = self.handler_tx.send(HandlerMessage::SubscribeChannel(
+                                channel_id, name, regex, filter, matcher,
+                            ));
+                        }
+                        Err(err) => {
+                            let _ = msg
+                                .channel_id
+                                .say(
+                                    &context.http,
+                                    format!(
+                                        "Invalid filter `{}`: {}. \
+                                         e.g. `!dcsbot subscribe mission:syria AND players>=4`",
+                                        filter_text, err
+                                    ),
+                                )
+                                .await;
+                        }
+                    }
                 } else {
                     let _ = msg
                         .channel_id
@@ -80,17 +143,245 @@ impl EventHandler for Handler {
                 }
             }
             Some("unsubscribe") => {
+                let name = components.next().map(str::to_string);
+                let _ = self
+                    .handler_tx
+                    .send(HandlerMessage::UnsubscribeChannel(channel_id, name));
+            }
+            Some("list") => {
                 let _ = self
                     .handler_tx
-                    .send(HandlerMessage::UnsubscribeChannel(channel_id));
+                    .send(HandlerMessage::ListSubscriptions(channel_id, guild_id));
+            }
+            Some("releases") => match components.next() {
+                Some("on") => {
+                    // Accept either a role mention or a bare role id
+                    let role_id = msg.mention_roles.first().map(|role| role.0).or_else(|| {
+                        components.next().and_then(|role| {
+                            role.trim_matches(|c| c == '<' || c == '>' || c == '@' || c == '&')
+                                .parse()
+                                .ok()
+                        })
+                    });
+                    let _ = self
+                        .handler_tx
+                        .send(HandlerMessage::SubscribeReleases(channel_id, role_id));
+                }
+                Some("off") => {
+                    let _ = self
+                        .handler_tx
+                        .send(HandlerMessage::UnsubscribeReleases(channel_id));
+                }
+                _ => {
+                    let _ = msg
+                        .channel_id
+                        .say(
+                            &context.http,
+                            "Usage: `!dcsbot releases on [@role]` or `!dcsbot releases off`",
+                        )
+                        .await;
+                }
+            },
+            Some("history") => {
+                let mut words: Vec<&str> = components.filter(|word| !word.is_empty()).collect();
+                // A number at the end is the number of days to look back
+                let days = match words.last().and_then(|word| word.parse::<u32>().ok()) {
+                    Some(days) if words.len() > 1 => {
+                        words.pop();
+                        days
+                    }
+                    _ => 7,
+                };
+
+                if !words.is_empty() {
+                    let _ = self.handler_tx.send(HandlerMessage::History(
+                        channel_id,
+                        words.join(" "),
+                        days,
+                    ));
+                } else {
+                    let _ = msg
+                        .channel_id
+                        .say(
+                            &context.http,
+                            "Search filter missing. e.g. `!dcsbot history australia 7`",
+                        )
+                        .await;
+                }
             }
+            Some("chart") => {
+                let mut words: Vec<&str> = components.filter(|word| !word.is_empty()).collect();
+                let range = match words.last().copied() {
+                    Some("24h") => Some(24),
+                    Some("7d") => Some(24 * 7),
+                    Some("30d") => Some(24 * 30),
+                    _ => None,
+                };
+                if range.is_some() {
+                    words.pop();
+                }
+                let range = Duration::from_secs(range.unwrap_or(24) * 60 * 60);
+
+                if !words.is_empty() {
+                    let _ = self.handler_tx.send(HandlerMessage::Chart(
+                        channel_id,
+                        words.join(" "),
+                        range,
+                    ));
+                } else {
+                    let _ = msg
+                        .channel_id
+                        .say(
+                            &context.http,
+                            "Search filter missing. e.g. `!dcsbot chart australia 7d`",
+                        )
+                        .await;
+                }
+            }
+            Some("heatmap") => {
+                let mut words: Vec<&str> = components.filter(|word| !word.is_empty()).collect();
+                // A number at the end is the number of days to look back
+                let days = match words.last().and_then(|word| word.parse::<u32>().ok()) {
+                    Some(days) if words.len() > 1 => {
+                        words.pop();
+                        days
+                    }
+                    _ => 28,
+                };
+
+                if !words.is_empty() {
+                    let _ = self.handler_tx.send(HandlerMessage::Heatmap(
+                        channel_id,
+                        guild_id,
+                        words.join(" "),
+                        days,
+                    ));
+                } else {
+                    let _ = msg
+                        .channel_id
+                        .say(
+                            &context.http,
+                            "Search filter missing. e.g. `!dcsbot heatmap australia 28`",
+                        )
+                        .await;
+                }
+            }
+            Some("set") => match (components.next(), components.next()) {
+                // Settings for a subscription take its name last, which can
+                // be left off if the channel only has the one
+                (Some("chart"), Some(value @ ("on" | "off"))) => {
+                    let name = components.next().map(str::to_string);
+                    let _ = self.handler_tx.send(HandlerMessage::SetChart(
+                        channel_id,
+                        name,
+                        value == "on",
+                    ));
+                }
+                (Some("fuzzy"), Some(value @ ("on" | "off"))) => {
+                    let name = components.next().map(str::to_string);
+                    let _ = self.handler_tx.send(HandlerMessage::SetFuzzy(
+                        channel_id,
+                        name,
+                        value == "on",
+                    ));
+                }
+                (Some("sort"), Some(key)) => {
+                    let key = match key.parse::<SortKey>() {
+                        Ok(key) => key,
+                        Err(err) => {
+                            let _ = msg.channel_id.say(&context.http, err).await;
+                            return;
+                        }
+                    };
+                    // The direction is optional, so the next word might be
+                    // the subscription's name instead
+                    let mut next = components.next();
+                    let order = match next.map(str::parse::<SortOrder>) {
+                        Some(Ok(order)) => {
+                            next = components.next();
+                            order
+                        }
+                        _ => key.default_order(),
+                    };
+                    let _ = self.handler_tx.send(HandlerMessage::SetSort(
+                        channel_id,
+                        next.map(str::to_string),
+                        key,
+                        order,
+                    ));
+                }
+                (Some("limit"), Some(limit)) => match limit.parse() {
+                    Ok(limit) => {
+                        let name = components.next().map(str::to_string);
+                        let _ = self
+                            .handler_tx
+                            .send(HandlerMessage::SetLimit(channel_id, name, limit));
+                    }
+                    Err(_) => {
+                        let _ = msg
+                            .channel_id
+                            .say(
+                                &context.http,
+                                "The limit should be a number, e.g. `!dcsbot set limit 20`",
+                            )
+                            .await;
+                    }
+                },
+                (Some("mode"), Some(mode @ ("listing" | "servers"))) => {
+                    let mode = match mode {
+                        "servers" => SubMode::Servers,
+                        _ => SubMode::Listing,
+                    };
+                    let name = components.next().map(str::to_string);
+                    let _ = self
+                        .handler_tx
+                        .send(HandlerMessage::SetMode(channel_id, name, mode));
+                }
+                (Some("style"), Some(style @ ("markdown" | "embeds" | "fields"))) => {
+                    let style = match style {
+                        "embeds" => Style::Embeds,
+                        "fields" => Style::Fields,
+                        _ => Style::Markdown,
+                    };
+                    let name = components.next().map(str::to_string);
+                    let _ = self
+                        .handler_tx
+                        .send(HandlerMessage::SetStyle(channel_id, name, style));
+                }
+                (Some("offline"), Some(offline @ ("delete" | "mark"))) => {
+                    let offline = match offline {
+                        "mark" => OfflineAction::Mark,
+                        _ => OfflineAction::Delete,
+                    };
+                    let name = components.next().map(str::to_string);
+                    let _ = self
+                        .handler_tx
+                        .send(HandlerMessage::SetOffline(channel_id, name, offline));
+                }
+                (Some("timezone"), Some(timezone)) => {
+                    let _ = self.handler_tx.send(HandlerMessage::SetTimezone(
+                        channel_id,
+                        guild_id,
+                        timezone.to_string(),
+                    ));
+                }
+                _ => {
+                    let _ = msg
+                        .channel_id
+                        .say(
+                            &context.http,
+                            "Usage: `!dcsbot set chart on|off [name]`, `!dcsbot set fuzzy on|off [name]`, `!dcsbot set sort players|name|version|uptime|changed [asc|desc] [name]`, `!dcsbot set limit <count> [name]`, `!dcsbot set mode listing|servers [name]`, `!dcsbot set style markdown|embeds|fields [name]`, `!dcsbot set offline delete|mark [name]` or `!dcsbot set timezone Europe/London`",
+                        )
+                        .await;
+                }
+            },
             Some(&_) => {}
             None => {
                 let _ = msg
                     .channel_id
                     .say(
                         &context.http,
-                        "dcsbot commands: ```!dcsbot subscribe <filter>\n!dcsbot unsubscribe```",
+                        "dcsbot commands: ```!dcsbot subscribe [as <name>] <filter>\n!dcsbot unsubscribe [name]\n!dcsbot list\n!dcsbot releases on [@role]\n!dcsbot releases off\n!dcsbot history <filter> [days]\n!dcsbot chart <filter> [24h|7d|30d]\n!dcsbot heatmap <filter> [days]\n!dcsbot set chart on|off [name]\n!dcsbot set fuzzy on|off [name]\n!dcsbot set sort <players|name|version|uptime|changed> [asc|desc] [name]\n!dcsbot set limit <count> [name]\n!dcsbot set mode listing|servers [name]\n!dcsbot set style markdown|embeds|fields [name]\n!dcsbot set offline delete|mark [name]\n!dcsbot set timezone <timezone>```",
                     )
                     .await;
             }
@@ -0,0 +1,688 @@
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::dcs::{ServerEvent, ServerId, Servers};
+
+// How often old samples are pruned and downsampled
+const MAINTENANCE_INTERVAL: i64 = 60 * 60;
+
+/**
+ * How much history to keep, and in how much detail
+ */
+#[derive(Clone, Debug)]
+pub struct HistorySettings {
+    pub path: String,
+    /// Anything older than this is deleted
+    pub retention: Duration,
+    /// Samples older than this are merged into one per server per hour
+    pub downsample_after: Duration,
+    /// How often to record player counts; polls in between are skipped
+    pub sample_interval: Duration,
+}
+
+/**
+ * A summary of one server's history over a period
+ */
+#[derive(Debug)]
+pub struct ServerStats {
+    pub name: String,
+    pub peak: u32,
+    /// Average players over the hours the server was online
+    pub average: f64,
+    pub online: Duration,
+    /// Missions and how many hours each was seen, most played first
+    pub missions: Vec<(String, u32)>,
+    /// Hours of the day (UTC) and their average players, busiest first
+    pub busiest_hours: Vec<(u32, f64)>,
+}
+
+/**
+ * What the bot has seen over time, kept in a SQLite database: player counts,
+ * missions and versions sampled from each poll, and the times each server
+ * came online and went offline
+ */
+pub struct History {
+    connection: Arc<Mutex<Connection>>,
+    settings: HistorySettings,
+    last_sample: i64,
+    last_maintenance: i64,
+}
+
+fn create_tables(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS servers (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            first_seen INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS samples (
+            server_id TEXT NOT NULL,
+            ts INTEGER NOT NULL,
+            players REAL NOT NULL,
+            peak INTEGER NOT NULL,
+            players_max INTEGER NOT NULL,
+            mission TEXT NOT NULL,
+            version TEXT NOT NULL,
+            hourly INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS samples_server_ts ON samples (server_id, ts);
+        CREATE INDEX IF NOT EXISTS samples_ts ON samples (ts);
+        CREATE TABLE IF NOT EXISTS sessions (
+            server_id TEXT NOT NULL,
+            start INTEGER NOT NULL,
+            end INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS sessions_server ON sessions (server_id, start);",
+    )
+}
+
+impl History {
+    /**
+     * Opens (or creates) the history database
+     */
+    pub fn open(settings: HistorySettings) -> rusqlite::Result<Self> {
+        let connection = Connection::open(&settings.path)?;
+        create_tables(&connection)?;
+
+        // We can't know exactly when servers that were online when we last
+        // stopped went offline, so end their sessions when we last saw them
+        let closed = connection.execute(
+            "UPDATE sessions SET end = MAX(start, (SELECT last_seen FROM servers WHERE id = server_id))
+             WHERE end IS NULL",
+            [],
+        )?;
+        if closed > 0 {
+            println!("Closed {} sessions left open by the last run", closed);
+        }
+
+        Ok(History {
+            connection: Arc::new(Mutex::new(connection)),
+            settings,
+            last_sample: 0,
+            last_maintenance: 0,
+        })
+    }
+
+    /**
+     * Runs a query on a blocking thread so it doesn't hold up the event loop
+     */
+    async fn with_connection<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap()))
+            .await
+            .expect("History query panicked")
+    }
+
+    /**
+     * Records a sample for every server in the snapshot, if it's been long
+     * enough since the last one
+     */
+    pub async fn record(&mut self, servers: &Servers) {
+        let now = Utc::now().timestamp();
+        if now - self.last_sample < self.settings.sample_interval.as_secs() as i64 {
+            return;
+        }
+        self.last_sample = now;
+
+        let rows: Vec<(String, String, u32, u32, String, String)> = servers
+            .SERVERS
+            .iter()
+            .map(|server| {
+                (
+                    server.id.to_string(),
+                    server.NAME.clone(),
+                    // The website counts the server itself as a player
+                    server.PLAYERS.saturating_sub(1),
+                    server.PLAYERS_MAX,
+                    server.MISSION_NAME.clone(),
+                    server.DCS_VERSION.to_string(),
+                )
+            })
+            .collect();
+
+        let result = self
+            .with_connection(move |connection| {
+                let tx = connection.transaction()?;
+                {
+                    let mut upsert = tx.prepare_cached(
+                        "INSERT INTO servers (id, name, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+                         ON CONFLICT (id) DO UPDATE SET name = ?2, last_seen = ?3",
+                    )?;
+                    let mut sample = tx.prepare_cached(
+                        "INSERT INTO samples (server_id, ts, players, peak, players_max, mission, version)
+                         VALUES (?1, ?2, ?3, ?3, ?4, ?5, ?6)",
+                    )?;
+                    for (id, name, players, players_max, mission, version) in &rows {
+                        upsert.execute(params![id, name, now])?;
+                        sample.execute(params![id, now, players, players_max, mission, version])?;
+                    }
+                }
+                tx.commit()
+            })
+            .await;
+
+        if let Err(err) = result {
+            println!("\x1b[31mError recording history: {:?}\x1b[0m", err);
+        }
+
+        if now - self.last_maintenance >= MAINTENANCE_INTERVAL {
+            self.last_maintenance = now;
+            self.maintain(now).await;
+        }
+    }
+
+    /**
+     * Records servers coming online and going offline
+     */
+    pub async fn record_events(&self, events: &[ServerEvent]) {
+        let now = Utc::now().timestamp();
+        let changes: Vec<(String, bool)> = events
+            .iter()
+            .filter_map(|event| match event {
+                ServerEvent::Online(server) => Some((server.id.to_string(), true)),
+                ServerEvent::Offline(server) => Some((server.id.to_string(), false)),
+                _ => None,
+            })
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+
+        let result = self
+            .with_connection(move |connection| {
+                let tx = connection.transaction()?;
+                for (id, online) in &changes {
+                    // Close anything still open first, in case we missed an event
+                    tx.execute(
+                        "UPDATE sessions SET end = ?2 WHERE server_id = ?1 AND end IS NULL",
+                        params![id, now],
+                    )?;
+                    if *online {
+                        tx.execute(
+                            "INSERT INTO sessions (server_id, start) VALUES (?1, ?2)",
+                            params![id, now],
+                        )?;
+                    }
+                }
+                tx.commit()
+            })
+            .await;
+
+        if let Err(err) = result {
+            println!("\x1b[31mError recording sessions: {:?}\x1b[0m", err);
+        }
+    }
+
+    /**
+     * Deletes history past the retention period, and merges older samples
+     * into one per server per hour
+     */
+    async fn maintain(&self, now: i64) {
+        let retention_cutoff = now - self.settings.retention.as_secs() as i64;
+        let downsample_cutoff = now - self.settings.downsample_after.as_secs() as i64;
+        // Only whole hours, so an hour is never merged twice
+        let downsample_cutoff = downsample_cutoff - downsample_cutoff.rem_euclid(3600);
+
+        let result = self
+            .with_connection(move |connection| {
+                let tx = connection.transaction()?;
+                let deleted = tx.execute("DELETE FROM samples WHERE ts < ?1", [retention_cutoff])?
+                    + tx.execute(
+                        "DELETE FROM sessions WHERE end IS NOT NULL AND end < ?1",
+                        [retention_cutoff],
+                    )?
+                    + tx.execute(
+                        "DELETE FROM servers WHERE last_seen < ?1",
+                        [retention_cutoff],
+                    )?;
+
+                // Each hour keeps the mission and version from its last sample
+                tx.execute(
+                    "WITH hours AS (
+                        SELECT server_id, (ts / 3600) * 3600 AS hour, AVG(players) AS players,
+                               MAX(peak) AS peak, MAX(players_max) AS players_max, MAX(ts) AS last
+                        FROM samples WHERE hourly = 0 AND ts < ?1
+                        GROUP BY server_id, ts / 3600
+                     )
+                     INSERT INTO samples (server_id, ts, players, peak, players_max, mission, version, hourly)
+                     SELECT hours.server_id, hours.hour, hours.players, hours.peak, hours.players_max,
+                            samples.mission, samples.version, 1
+                     FROM hours JOIN samples
+                       ON samples.server_id = hours.server_id AND samples.ts = hours.last
+                      AND samples.hourly = 0",
+                    [downsample_cutoff],
+                )?;
+                let merged = tx.execute(
+                    "DELETE FROM samples WHERE hourly = 0 AND ts < ?1",
+                    [downsample_cutoff],
+                )?;
+                tx.commit()?;
+                Ok((deleted, merged))
+            })
+            .await;
+
+        match result {
+            Ok((deleted, merged)) => println!(
+                "History: deleted {} old rows, merged {} samples into hourly ones",
+                deleted, merged
+            ),
+            Err(err) => println!("\x1b[31mError maintaining history: {:?}\x1b[0m", err),
+        }
+    }
+
+    /**
+     * Every server we have history for, with the last name we saw it under
+     */
+    pub async fn servers(&self) -> rusqlite::Result<Vec<(ServerId, String)>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT id, name FROM servers")?;
+            let rows = statement.query_map([], |row| {
+                Ok((ServerId::from(row.get::<_, String>(0)?), row.get(1)?))
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    pub fn sample_interval(&self) -> Duration {
+        self.settings.sample_interval
+    }
+
+    /**
+     * Each server's player counts since the given time, averaged over
+     * buckets of the given number of seconds
+     */
+    pub async fn players(
+        &self,
+        servers: Vec<(ServerId, String)>,
+        since: i64,
+        bucket: i64,
+    ) -> rusqlite::Result<Vec<(String, Vec<(i64, f64)>)>> {
+        let bucket = bucket.max(1);
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT (ts / ?3) * ?3 AS time, AVG(players)
+                 FROM samples WHERE server_id = ?1 AND ts >= ?2
+                 GROUP BY time ORDER BY time",
+            )?;
+            servers
+                .into_iter()
+                .map(|(id, name)| {
+                    let points = statement
+                        .query_map(params![id.to_string(), since, bucket], |row| {
+                            Ok((row.get(0)?, row.get(1)?))
+                        })?
+                        .collect::<rusqlite::Result<Vec<(i64, f64)>>>()?;
+                    Ok((name, points))
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /**
+     * The total players across the given servers in each hour since the
+     * given time, keyed by the start of the hour. Hours without any samples
+     * are left out
+     */
+    pub async fn hourly_players(
+        &self,
+        servers: Vec<ServerId>,
+        since: i64,
+    ) -> rusqlite::Result<HashMap<i64, f64>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT (ts / 3600) * 3600 AS hour, AVG(players)
+                 FROM samples WHERE server_id = ?1 AND ts >= ?2
+                 GROUP BY hour",
+            )?;
+            let mut hours = HashMap::new();
+            for id in servers {
+                let rows = statement.query_map(params![id.to_string(), since], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?))
+                })?;
+                for row in rows {
+                    let (hour, players) = row?;
+                    *hours.entry(hour).or_insert(0.0) += players;
+                }
+            }
+            Ok(hours)
+        })
+        .await
+    }
+
+    /**
+     * The hours since the given time that we recorded anything in, whether or
+     * not it was any particular server, keyed by the start of the hour. Hours
+     * missing from this are ones we weren't running (or polling) for
+     */
+    pub async fn recorded_hours(&self, since: i64) -> rusqlite::Result<HashSet<i64>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection
+                .prepare("SELECT DISTINCT (ts / 3600) * 3600 FROM samples WHERE ts >= ?1")?;
+            let rows = statement.query_map(params![since], |row| row.get(0))?;
+            rows.collect()
+        })
+        .await
+    }
+
+    /**
+     * Summarises each server's history since the given time
+     */
+    pub async fn stats(
+        &self,
+        servers: Vec<(ServerId, String)>,
+        since: i64,
+    ) -> rusqlite::Result<Vec<ServerStats>> {
+        let now = Utc::now().timestamp();
+        self.with_connection(move |connection| {
+            servers
+                .into_iter()
+                .map(|(id, name)| server_stats(connection, id, name, since, now))
+                .collect()
+        })
+        .await
+    }
+}
+
+fn server_stats(
+    connection: &Connection,
+    id: ServerId,
+    name: String,
+    since: i64,
+    now: i64,
+) -> rusqlite::Result<ServerStats> {
+    let key = id.to_string();
+
+    // Samples come in different resolutions (raw and hourly), so averages
+    // are taken over hours to weigh them evenly
+    let (peak, average) = connection.query_row(
+        "WITH hours AS (
+            SELECT AVG(players) AS players, MAX(peak) AS peak
+            FROM samples WHERE server_id = ?1 AND ts >= ?2
+            GROUP BY ts / 3600
+         )
+         SELECT COALESCE(MAX(peak), 0), COALESCE(AVG(players), 0) FROM hours",
+        params![key, since],
+        |row| Ok((row.get::<_, u32>(0)?, row.get::<_, f64>(1)?)),
+    )?;
+
+    let online = connection.query_row(
+        "SELECT COALESCE(SUM(MIN(COALESCE(end, ?3), ?3) - MAX(start, ?2)), 0)
+         FROM sessions WHERE server_id = ?1 AND COALESCE(end, ?3) > ?2",
+        params![key, since, now],
+        |row| row.get::<_, i64>(0),
+    )?;
+
+    let mut statement = connection.prepare_cached(
+        "SELECT mission, COUNT(DISTINCT ts / 3600) AS hours
+         FROM samples WHERE server_id = ?1 AND ts >= ?2
+         GROUP BY mission ORDER BY hours DESC LIMIT 5",
+    )?;
+    let missions = statement
+        .query_map(params![key, since], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(String, u32)>>>()?;
+
+    let mut statement = connection.prepare_cached(
+        "WITH hours AS (
+            SELECT ts / 3600 AS hour, AVG(players) AS players
+            FROM samples WHERE server_id = ?1 AND ts >= ?2
+            GROUP BY ts / 3600
+         )
+         SELECT hour % 24 AS hour_of_day, AVG(players) AS average
+         FROM hours GROUP BY hour_of_day
+         HAVING average > 0 ORDER BY average DESC LIMIT 3",
+    )?;
+    let busiest_hours = statement
+        .query_map(params![key, since], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(u32, f64)>>>()?;
+
+    Ok(ServerStats {
+        name,
+        peak,
+        average,
+        online: Duration::from_secs(online.max(0) as u64),
+        missions,
+        busiest_hours,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3600;
+    const DAY: i64 = 24 * HOUR;
+    // On the hour, so the cutoffs below are too
+    const NOW: i64 = 472_222 * HOUR;
+
+    fn history() -> History {
+        let connection = Connection::open_in_memory().unwrap();
+        create_tables(&connection).unwrap();
+        History {
+            connection: Arc::new(Mutex::new(connection)),
+            settings: HistorySettings {
+                path: String::new(),
+                retention: Duration::from_secs(30 * DAY as u64),
+                downsample_after: Duration::from_secs(2 * DAY as u64),
+                sample_interval: Duration::from_secs(60),
+            },
+            last_sample: 0,
+            last_maintenance: 0,
+        }
+    }
+
+    fn execute(history: &History, sql: &str, params: impl rusqlite::Params) {
+        history
+            .connection
+            .lock()
+            .unwrap()
+            .execute(sql, params)
+            .unwrap();
+    }
+
+    fn sample(history: &History, id: &str, ts: i64, players: u32, mission: &str) {
+        execute(
+            history,
+            "INSERT INTO samples (server_id, ts, players, peak, players_max, mission, version)
+             VALUES (?1, ?2, ?3, ?3, 16, ?4, '2.9.3.51704')",
+            params![id, ts, players, mission],
+        );
+    }
+
+    fn session(history: &History, id: &str, start: i64, end: Option<i64>) {
+        execute(
+            history,
+            "INSERT INTO sessions (server_id, start, end) VALUES (?1, ?2, ?3)",
+            params![id, start, end],
+        );
+    }
+
+    fn server(history: &History, id: &str, last_seen: i64) {
+        execute(
+            history,
+            "INSERT INTO servers (id, name, first_seen, last_seen) VALUES (?1, ?1, 0, ?2)",
+            params![id, last_seen],
+        );
+    }
+
+    /**
+     * (server, ts, players, peak, mission, hourly) for every sample, in order
+     */
+    fn samples(history: &History) -> Vec<(String, i64, f64, u32, String, bool)> {
+        let connection = history.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT server_id, ts, players, peak, mission, hourly FROM samples
+                 ORDER BY ts, server_id",
+            )
+            .unwrap();
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })
+            .unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    fn count(history: &History, table: &str) -> i64 {
+        history
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    fn row(
+        id: &str,
+        ts: i64,
+        players: f64,
+        peak: u32,
+        mission: &str,
+        hourly: bool,
+    ) -> (String, i64, f64, u32, String, bool) {
+        (
+            id.to_string(),
+            ts,
+            players,
+            peak,
+            mission.to_string(),
+            hourly,
+        )
+    }
+
+    #[tokio::test]
+    async fn deletes_past_retention_and_downsamples() {
+        let history = history();
+        let retention = NOW - 30 * DAY;
+        let downsample = NOW - 2 * DAY;
+        let hour = downsample - HOUR;
+
+        sample(&history, "a", retention - 1, 5, "Old");
+        sample(&history, "a", retention, 7, "Old");
+        sample(&history, "a", hour, 2, "First");
+        sample(&history, "a", hour + 600, 4, "First");
+        sample(&history, "a", hour + 1200, 6, "Second");
+        sample(&history, "b", hour + 300, 10, "Other");
+        sample(&history, "a", downsample, 8, "Second");
+        sample(&history, "a", NOW - 60, 3, "Second");
+
+        session(&history, "a", retention - DAY, Some(retention - 1));
+        session(&history, "a", retention - DAY, Some(retention));
+        session(&history, "b", retention - DAY, None);
+
+        server(&history, "gone", retention - 1);
+        server(&history, "a", NOW);
+
+        history.maintain(NOW).await;
+        let expected = vec![
+            row("a", retention, 7.0, 7, "Old", true),
+            row("a", hour, 4.0, 6, "Second", true),
+            row("b", hour, 10.0, 10, "Other", true),
+            row("a", downsample, 8.0, 8, "Second", false),
+            row("a", NOW - 60, 3.0, 3, "Second", false),
+        ];
+        assert_eq!(samples(&history), expected);
+        assert_eq!(count(&history, "sessions"), 2);
+        assert_eq!(count(&history, "servers"), 1);
+
+        // Hours that were merged already are left alone
+        history.maintain(NOW).await;
+        assert_eq!(samples(&history), expected);
+    }
+
+    #[tokio::test]
+    async fn summarises_mixed_resolutions() {
+        let history = history();
+        let since = NOW - 7 * DAY;
+
+        // Before the period
+        sample(&history, "a", since - 1, 100, "Ignored");
+        // An hour that was downsampled already...
+        execute(
+            &history,
+            "INSERT INTO samples (server_id, ts, players, peak, players_max, mission, version, hourly)
+             VALUES ('a', ?1, 4, 6, 16, 'First', '2.9.3.51704', 1)",
+            [since],
+        );
+        // ...one still in raw samples, which counts the same...
+        sample(&history, "a", since + HOUR, 6, "Second");
+        sample(&history, "a", since + HOUR + 60, 10, "Second");
+        sample(&history, "a", since + HOUR + 120, 8, "Second");
+        // ...and an empty one
+        sample(&history, "a", since + 2 * HOUR, 0, "Second");
+        // Another server's don't count
+        sample(&history, "b", since + HOUR, 50, "Other");
+
+        session(&history, "a", since - 100, Some(since + HOUR));
+        session(&history, "a", NOW - 1800, None);
+        session(&history, "a", since - DAY, Some(since - 1));
+
+        let connection = history.connection.lock().unwrap();
+        let stats = server_stats(
+            &connection,
+            ServerId::from("a".to_string()),
+            "Alpha".to_string(),
+            since,
+            NOW,
+        )
+        .unwrap();
+
+        assert_eq!(stats.name, "Alpha");
+        assert_eq!(stats.peak, 10);
+        assert_eq!(stats.average, 4.0);
+        assert_eq!(stats.online, Duration::from_secs((HOUR + 1800) as u64));
+        assert_eq!(
+            stats.missions,
+            [("Second".to_string(), 2), ("First".to_string(), 1)]
+        );
+        let hour_of_day = |ts: i64| ((ts / HOUR) % 24) as u32;
+        assert_eq!(
+            stats.busiest_hours,
+            [(hour_of_day(since + HOUR), 8.0), (hour_of_day(since), 4.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn tells_empty_hours_from_unrecorded_ones() {
+        let history = history();
+        let since = NOW - DAY;
+        sample(&history, "a", since - 60, 5, "Before");
+        sample(&history, "a", since + 60, 5, "First");
+        sample(&history, "a", since + 120, 5, "First");
+        // Nothing of a's in the next hour, but we were still recording
+        sample(&history, "b", since + HOUR + 60, 0, "Other");
+        sample(&history, "a", since + 3 * HOUR, 2, "Second");
+
+        let hours = history
+            .hourly_players(vec![ServerId::from("a".to_string())], since)
+            .await
+            .unwrap();
+        assert_eq!(
+            hours,
+            HashMap::from([(since, 5.0), (since + 3 * HOUR, 2.0)])
+        );
+        assert_eq!(
+            history.recorded_hours(since).await.unwrap(),
+            HashSet::from([since, since + HOUR, since + 3 * HOUR])
+        );
+    }
+}
@@ -1,9 +1,27 @@
-use clap::Parser;
+use clap::{ArgEnum, Parser};
+use std::time::Duration;
 use tokio::sync::mpsc;
 
 mod bot;
+mod chart;
+mod config;
 mod dcs;
+mod embed;
+mod filter;
+mod fuzzy;
 mod handler;
+mod history;
+mod normalize;
+
+const DAY: u64 = 60 * 60 * 24;
+
+#[derive(ArgEnum, Clone, Debug)]
+enum Source {
+    /// Scrape digitalcombatsimulator.com (needs a username and password)
+    Website,
+    /// Read listings from a JSON file or a directory of JSON files
+    File,
+}
 
 /**
  * Config for clap's command line argument thingy
@@ -11,12 +29,12 @@ mod handler;
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Your DCS username (required)
-    #[clap(short)]
+    /// Your DCS username (required for the website source)
+    #[clap(short, default_value = "")]
     username: String,
 
-    /// Your DCS password (required)
-    #[clap(short)]
+    /// Your DCS password (required for the website source)
+    #[clap(short, default_value = "")]
     password: String,
 
     /// Discord bot token
@@ -26,6 +44,97 @@ struct Args {
     /// Config file location
     #[clap(short, default_value = "")]
     filepath: String,
+
+    /// Where server listings come from
+    #[clap(long, arg_enum, default_value = "website")]
+    source: Source,
+
+    /// JSON file or directory of JSON files to read (for the file source)
+    #[clap(long, default_value = "")]
+    source_path: String,
+
+    /// JSON file with DCS website endpoints (base_url, login_path, servers_path,
+    /// changelog_path); the flags below override it
+    #[clap(long, default_value = "")]
+    endpoints: String,
+
+    /// DCS website base URL, e.g. http://localhost:8080
+    #[clap(long)]
+    dcs_url: Option<String>,
+
+    /// Path of the login page
+    #[clap(long)]
+    login_path: Option<String>,
+
+    /// Path of the server list
+    #[clap(long)]
+    servers_path: Option<String>,
+
+    /// Path of the changelog page
+    #[clap(long)]
+    changelog_path: Option<String>,
+
+    /// Seconds to wait after the first failure; doubles with each failure
+    #[clap(long, default_value = "30")]
+    retry_base: u64,
+
+    /// Longest wait between retries, in seconds
+    #[clap(long, default_value = "1800")]
+    retry_max: u64,
+
+    /// Fraction of each wait to randomly add or remove
+    #[clap(long, default_value = "0.2")]
+    retry_jitter: f64,
+
+    /// Minimum wait after the website rejects our login, in seconds
+    #[clap(long, default_value = "3600")]
+    auth_retry: u64,
+
+    /// Where to remember server identities (names and addresses) across
+    /// restarts; empty to keep them in memory only
+    #[clap(long, default_value = "identities.json")]
+    identities: String,
+
+    /// SQLite database to record server history in; empty to not keep history
+    #[clap(long, default_value = "history.db")]
+    history: String,
+
+    /// Days of history to keep
+    #[clap(long, default_value = "90")]
+    history_retention: u64,
+
+    /// Days after which history is merged into hourly averages
+    #[clap(long, default_value = "7")]
+    history_downsample: u64,
+
+    /// Seconds between history samples
+    #[clap(long, default_value = "300")]
+    history_interval: u64,
+}
+
+/**
+ * Builds the website endpoints from the endpoints file (if any) and the
+ * command line overrides
+ */
+fn endpoints(args: &Args) -> Result<dcs::Endpoints, String> {
+    let mut endpoints = match args.endpoints.as_str() {
+        "" => dcs::Endpoints::default(),
+        path => dcs::Endpoints::load(path)?,
+    };
+
+    if let Some(url) = &args.dcs_url {
+        endpoints.base_url = url.clone();
+    }
+    if let Some(path) = &args.login_path {
+        endpoints.login_path = path.clone();
+    }
+    if let Some(path) = &args.servers_path {
+        endpoints.servers_path = path.clone();
+    }
+    if let Some(path) = &args.changelog_path {
+        endpoints.changelog_path = path.clone();
+    }
+    Ok(endpoints)
 }
 
 #[tokio::main]
@@ -34,11 +143,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     let (servers_tx, servers_rx) = mpsc::channel(1);
 
+    let source: Box<dyn dcs::ServerSource> = match args.source {
+        Source::Website => Box::new(dcs::WebsiteSource::new(
+            endpoints(&args)?,
+            args.username,
+            args.password,
+        )),
+        Source::File => Box::new(dcs::FileSource::new(args.source_path)),
+    };
+
+    let policy = dcs::RetryPolicy {
+        base: Duration::from_secs(args.retry_base),
+        max: Duration::from_secs(args.retry_max),
+        jitter: args.retry_jitter,
+        auth_delay: Duration::from_secs(args.auth_retry),
+    };
+
+    let identities = dcs::IdentityTracker::load(args.identities);
+
+    let history = match args.history.as_str() {
+        "" => None,
+        path => Some(history::History::open(history::HistorySettings {
+            path: path.to_string(),
+            retention: Duration::from_secs(args.history_retention * DAY),
+            downsample_after: Duration::from_secs(args.history_downsample * DAY),
+            sample_interval: Duration::from_secs(args.history_interval),
+        })?),
+    };
+
     tokio::spawn(async move {
-        dcs::start(args.username, args.password, servers_tx).await;
+        dcs::start(source, policy, identities, servers_tx).await;
     });
 
-    bot::start(args.token, args.filepath, servers_rx).await;
+    bot::start(args.token, args.filepath, history, servers_rx).await;
 
     // Reaching here would be bad; consider notifying
     println!("Exiting");
@@ -0,0 +1,190 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_properties::{GeneralCategory, UnicodeGeneralCategory};
+
+// The website puts a space into long words after this many characters, so
+// they can wrap in its server list
+const ED_BREAK: usize = 20;
+
+/**
+ * Turns a name (or mission, or description) from the server listing into
+ * something fit to show: HTML entities decoded, the spaces the website
+ * inserts taken back out, and decorations and invisible characters removed.
+ * Letters in any script are kept
+ */
+pub fn display_name(raw: &str) -> String {
+    clean(&join_breaks(&decode_entities(raw)))
+}
+
+/**
+ * Normalises text for comparing: compatibility forms (fullwidth and
+ * "mathematical bold" letters etc.) become plain ones, decorations are
+ * dropped and whitespace is collapsed
+ */
+pub fn clean(text: &str) -> String {
+    let text: String = text
+        .nfkc()
+        .map(|c| if is_blank(c) { ' ' } else { c })
+        .filter(|c| !is_decoration(*c))
+        .collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/**
+ * Cleans and case folds text, so two strings that differ only in case (in
+ * any script, e.g. "STRASSE" and "Straße") fold to the same thing
+ */
+pub fn fold(text: &str) -> String {
+    caseless::default_case_fold_str(&clean(text))
+        .nfkc()
+        .collect()
+}
+
+/**
+ * Decodes every HTML entity, named or numeric. Some names come through
+ * encoded twice (`&amp;quot;`), so a second pass picks those up
+ */
+fn decode_entities(raw: &str) -> String {
+    let once = html_escape::decode_html_entities(raw);
+    if once.contains('&') && once != raw {
+        html_escape::decode_html_entities(&once).into_owned()
+    } else {
+        once.into_owned()
+    }
+}
+
+/**
+ * Takes out the spaces the website inserts into long words. A space straight
+ * after a run of exactly ED_BREAK characters is most likely one of those,
+ * unless it looks like a real gap between words: before a capital following
+ * a lower case letter ("...Internationalization Server"), or around the
+ * separators people space out in names ("... | PvE")
+ */
+fn join_breaks(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut joined = String::with_capacity(text.len());
+    let mut run = 0;
+    for (index, c) in chars.iter().enumerate() {
+        if *c == ' ' && run == ED_BREAK {
+            let before = chars[index - 1];
+            let after = chars.get(index + 1).copied();
+            if after.is_some_and(|after| is_inserted_break(before, after)) {
+                run = 0;
+                continue;
+            }
+        }
+        run = if c.is_whitespace() { 0 } else { run + 1 };
+        joined.push(*c);
+    }
+    joined
+}
+
+/**
+ * Whitespace, and the "blank" characters people use as invisible spaces
+ */
+fn is_blank(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '\u{115f}' | '\u{1160}' | '\u{3164}' | '\u{ffa0}' | '\u{2800}'
+        )
+}
+
+fn is_inserted_break(before: char, after: char) -> bool {
+    if after.is_whitespace() {
+        return false;
+    }
+    if before.is_lowercase() && after.is_uppercase() {
+        return false;
+    }
+    !"|[(-:/".contains(after) && !"|])-:,".contains(before)
+}
+
+/**
+ * Characters that only decorate a name or hide in it: symbols and emoji
+ * (with their variation selectors and skin tones), control and format
+ * characters such as zero-width spaces and right-to-left overrides, and
+ * private use characters
+ */
+fn is_decoration(c: char) -> bool {
+    match c.general_category() {
+        GeneralCategory::OtherSymbol
+        | GeneralCategory::EnclosingMark
+        | GeneralCategory::Control
+        | GeneralCategory::Format
+        | GeneralCategory::PrivateUse
+        | GeneralCategory::Surrogate
+        | GeneralCategory::Unassigned => true,
+        _ => matches!(
+            c,
+            '\u{fe00}'..='\u{fe0f}'
+                | '\u{e0100}'..='\u{e01ef}'
+                | '\u{1f3fb}'..='\u{1f3ff}'
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Replaces `\u{...}` escapes, which the corpus uses for characters that
+     * can't be seen
+     */
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("\\u{") {
+            unescaped.push_str(&rest[..start]);
+            let end = start + rest[start..].find('}').expect("Unclosed escape");
+            let code = u32::from_str_radix(&rest[start + 3..end], 16).expect("Bad escape");
+            unescaped.push(char::from_u32(code).expect("Bad character"));
+            rest = &rest[end + 1..];
+        }
+        unescaped.push_str(rest);
+        unescaped
+    }
+
+    #[test]
+    fn cleans_server_name_corpus() {
+        let corpus = include_str!("../tests/fixtures/server_names.tsv");
+        let mut failures = vec![];
+        for line in corpus.lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (raw, expected) = line.split_once('\t').expect("Missing tab");
+            let (raw, expected) = (unescape(raw), unescape(expected));
+            let actual = display_name(&raw);
+            if actual != expected {
+                failures.push(format!(
+                    "{:?}: expected {:?}, got {:?}",
+                    raw, expected, actual
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn joins_every_inserted_break() {
+        assert_eq!(
+            display_name("Aaaaaaaaaaaaaaaaaaaa bbbbbbbbbbbbbbbbbbbb ccccc"),
+            "Aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbccccc"
+        );
+        // Counted in characters, not bytes
+        assert_eq!(
+            display_name("Équipe_Française_Vol à_voile"),
+            "Équipe_Française_Volà_voile"
+        );
+    }
+
+    #[test]
+    fn folds_case_in_any_script() {
+        assert_eq!(fold("GROSSER Server"), fold("Großer server"));
+        assert_eq!(fold("РУССКИЙ"), "русский");
+        assert_eq!(fold("ΣΟΦΙΑ"), fold("σοφια"));
+        assert_eq!(fold("ＪＴＦ  Training"), "jtf training");
+        assert_eq!(fold("𝐁𝐢𝐠 Server"), "big server");
+    }
+}